@@ -10,6 +10,9 @@ pub struct UserInfo {}
 #[derive(Clone)]
 pub struct StatusInfo {}
 
+#[derive(Clone)]
+pub struct ServiceInfo {}
+
 #[tokio::main]
 async fn main() {
   Shell::new(UserInfo {})
@@ -20,7 +23,11 @@ async fn main() {
       "status",
       Scripts::new(StatusInfo {})
         .add_command(status_print)
-        .add_command(status_log),
+        .add_command(status_log)
+        .add_subcommand(
+          "service",
+          Scripts::new(ServiceInfo {}).add_command(service_restart),
+        ),
     )
     .run()
     .await;
@@ -49,6 +56,22 @@ pub async fn status_log(_: StatusInfo, _: Option<()>) -> mysh::Result<()> {
   Ok(())
 }
 
+#[derive(CommandArg, Serialize, Deserialize, Debug, Clone)]
+pub struct RestartArgs {
+  #[arg(positional)]
+  name: String,
+}
+
+#[command(
+  name = "restart",
+  description = "Restarts a service",
+  long_description = "Restarts a service"
+)]
+pub async fn service_restart(_: ServiceInfo, args: RestartArgs) -> mysh::Result<()> {
+  println!("restarting {}", args.name);
+  Ok(())
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct MyJson {
   hi: String,
@@ -1,6 +1,74 @@
 use proc_macro::TokenStream;
 use quote::{ToTokens, quote};
-use syn::{Data, DataStruct, DeriveInput, Fields, parse_macro_input};
+use syn::{
+  Data, DataStruct, DeriveInput, Fields, LitChar, LitStr, Type, parse_macro_input, spanned::Spanned,
+};
+
+/// Options set with `#[arg(...)]` on a field.
+#[derive(Default)]
+struct FieldAttrs {
+  positional: bool,
+  input: bool,
+  secret: bool,
+  short: Option<char>,
+  aliases: Vec<String>,
+  delimiter: Option<char>,
+}
+
+impl FieldAttrs {
+  fn parse(field: &syn::Field, name: &str) -> syn::Result<Self> {
+    let mut attrs = FieldAttrs::default();
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("arg")) {
+      attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("positional") {
+          attrs.positional = true;
+          return Ok(());
+        }
+        if meta.path.is_ident("input") {
+          attrs.input = true;
+          return Ok(());
+        }
+        if meta.path.is_ident("secret") {
+          attrs.secret = true;
+          return Ok(());
+        }
+        if meta.path.is_ident("short") {
+          // `short` alone uses the first letter of the field
+          let short = match meta.value() {
+            Ok(value) => value.parse::<LitChar>()?.value(),
+            Err(_) => name.chars().next().expect("empty field name"),
+          };
+          if short == 'h' || !short.is_ascii_alphanumeric() {
+            return Err(meta.error("short flags must be a letter or digit other than `h`"));
+          }
+          attrs.short = Some(short);
+          return Ok(());
+        }
+        if meta.path.is_ident("delimiter") {
+          attrs.delimiter = Some(meta.value()?.parse::<LitChar>()?.value());
+          return Ok(());
+        }
+        if meta.path.is_ident("alias") {
+          attrs.aliases.push(meta.value()?.parse::<LitStr>()?.value());
+          return Ok(());
+        }
+        Err(meta.error("unsupported arg attribute"))
+      })?;
+    }
+    Ok(attrs)
+  }
+}
+
+fn last_segment_is(ty: &Type, name: &str) -> bool {
+  match ty {
+    Type::Path(type_path) => type_path
+      .path
+      .segments
+      .last()
+      .is_some_and(|segment| segment.ident == name),
+    _ => false,
+  }
+}
 
 pub fn derive(input: TokenStream) -> TokenStream {
   let input = parse_macro_input!(input as DeriveInput);
@@ -13,29 +81,92 @@ pub fn derive(input: TokenStream) -> TokenStream {
     _ => panic!("expected a struct with named fields"),
   };
 
-  let mut fields_as_shell_args = vec![];
+  let mut arg_specs = vec![];
+  let mut variadic_seen = false;
+  let mut shorts = vec![];
+  let mut input_seen = false;
   for field in fields {
     let Some(ident) = &field.ident else {
       continue;
     };
+    let name = ident.to_string();
+    let attrs = match FieldAttrs::parse(field, &name) {
+      Ok(attrs) => attrs,
+      Err(err) => return err.to_compile_error().into(),
+    };
     let normalized_ty = field
       .ty
       .to_token_stream()
       .to_string()
       .replace(" >", ">")
       .replace(" < ", "<");
-    let arg = format!("--{ident}: {normalized_ty}");
-    fields_as_shell_args.push(quote! {
-      #arg.to_string()
-    });
+
+    if attrs.positional && variadic_seen {
+      return syn::Error::new(
+        field.span(),
+        "a positional Vec must be the last positional field",
+      )
+      .to_compile_error()
+      .into();
+    }
+    if attrs.input && (attrs.positional || input_seen) {
+      return syn::Error::new(
+        field.span(),
+        "only one field can take piped input, and it can't be positional",
+      )
+      .to_compile_error()
+      .into();
+    }
+    input_seen |= attrs.input;
+    if attrs.secret && attrs.positional {
+      // History masks the word after a flag, a bare word can't be told apart
+      return syn::Error::new(field.span(), "secret fields can't be positional")
+        .to_compile_error()
+        .into();
+    }
+
+    let variadic = attrs.positional && last_segment_is(&field.ty, "Vec");
+    variadic_seen |= variadic;
+
+    let mut spec = quote! { mysh::ArgSpec::new(#name, #normalized_ty) };
+    if variadic {
+      spec.extend(quote! { .variadic() });
+    } else if attrs.positional {
+      spec.extend(quote! { .positional() });
+    }
+    if attrs.input {
+      spec.extend(quote! { .input() });
+    }
+    if attrs.secret {
+      spec.extend(quote! { .secret() });
+    }
+    if let Some(short) = attrs.short {
+      if shorts.contains(&short) {
+        return syn::Error::new(field.span(), format!("short flag -{short} is already used"))
+          .to_compile_error()
+          .into();
+      }
+      shorts.push(short);
+      spec.extend(quote! { .short(#short) });
+    }
+    for alias in &attrs.aliases {
+      spec.extend(quote! { .alias(#alias) });
+    }
+    if let Some(delimiter) = attrs.delimiter {
+      spec.extend(quote! { .delimiter(#delimiter) });
+    }
+    arg_specs.push(spec);
   }
-  // let args = fields_as_shell_args.join("");
 
   let name = input.ident;
   let expanded = quote! {
     impl mysh::CommandArg for #name {
       fn display_help() -> Vec<String> {
-        vec![#(#fields_as_shell_args),*]
+        Self::args().iter().map(mysh::ArgSpec::help_line).collect()
+      }
+
+      fn args() -> Vec<mysh::ArgSpec> {
+        vec![#(#arg_specs),*]
       }
     }
   };
@@ -173,8 +173,8 @@ pub fn command(attr: TokenStream, func: TokenStream) -> TokenStream {
           }
         }
 
-        let args = mysh::parse_arguments(argv)?;
         Ok(Box::pin(async move {
+          let args = mysh::parse_arguments_prompting(argv).await?;
           let r = #func_name::call(info, args).await?;
           Ok(mysh::json::to_value(r).context("Return value not json-able")?)
         }))
@@ -183,6 +183,10 @@ pub fn command(attr: TokenStream, func: TokenStream) -> TokenStream {
         use mysh::CommandArg;
         #args_ty_turbo::display_help()
       }
+      fn args(&self) -> Vec<mysh::ArgSpec> {
+        use mysh::CommandArg;
+        #args_ty_turbo::args()
+      }
     }
   };
 
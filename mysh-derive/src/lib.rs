@@ -5,7 +5,7 @@ mod command_derive;
 
 use proc_macro::TokenStream;
 
-#[proc_macro_derive(CommandArg)]
+#[proc_macro_derive(CommandArg, attributes(arg))]
 pub fn derive_command_arg(input: TokenStream) -> TokenStream {
   command_arg_derive::derive(input)
 }
@@ -0,0 +1,208 @@
+use serde::de::DeserializeOwned;
+use serde_json::{Number, Value};
+use serde_reflection::{ContainerFormat, Format, Named, Registry, Tracer, TracerConfig};
+
+/// A sample that parses as most string-backed types (eg. `Uuid`), so tracing
+/// doesn't bail out on types that validate their input.
+const SAMPLE_STR: &str = "00000000-0000-0000-0000-000000000000";
+
+/// The serde shape of a command's argument type, traced with
+/// serde-reflection. Used to convert each flag to the JSON value its field
+/// actually expects instead of guessing from the text.
+pub(crate) struct ArgSchema {
+  root: Format,
+  registry: Registry,
+}
+
+impl ArgSchema {
+  /// Returns `None` for types serde-reflection can't trace, eg. ones that
+  /// rely on `deserialize_any` (untagged enums, `json::Value`, flatten).
+  pub fn trace<T: DeserializeOwned>() -> Option<Self> {
+    let config = TracerConfig::default()
+      .is_human_readable(true)
+      .default_borrowed_str_value(SAMPLE_STR)
+      .default_string_value(SAMPLE_STR.to_string());
+    let mut tracer = Tracer::new(config);
+    let (root, _) = tracer.trace_simple_type::<T>().ok()?;
+    let registry = tracer.registry().ok()?;
+    Some(ArgSchema { root, registry })
+  }
+
+  fn resolve<'a>(&'a self, format: &'a Format) -> Option<&'a ContainerFormat> {
+    match format {
+      Format::TypeName(name) => self.registry.get(name),
+      _ => None,
+    }
+  }
+
+  pub fn root(&self) -> &Format {
+    &self.root
+  }
+
+  /// The named fields of the argument struct, looking through `Option`.
+  pub fn fields(&self) -> Option<&[Named<Format>]> {
+    let format = match &self.root {
+      Format::Option(inner) => inner.as_ref(),
+      format => format,
+    };
+    match self.resolve(format) {
+      Some(ContainerFormat::Struct(fields)) => Some(fields),
+      _ => None,
+    }
+  }
+
+  /// True when the argument type itself (not `Option<_>`) is a struct.
+  pub fn is_struct(&self) -> bool {
+    matches!(self.resolve(&self.root), Some(ContainerFormat::Struct(_)))
+  }
+
+  /// Finds the field for a flag, treating `-` and `_` as equivalent.
+  pub fn field(&self, flag: &str) -> Option<&Named<Format>> {
+    let flag = flag.replace('-', "_");
+    self
+      .fields()?
+      .iter()
+      .find(|field| field.name.replace('-', "_") == flag)
+  }
+
+  pub fn is_bool(&self, format: &Format) -> bool {
+    match format {
+      Format::Bool => true,
+      Format::Option(inner) => self.is_bool(inner),
+      _ => false,
+    }
+  }
+
+  /// Converts `raw` to the JSON value `format` deserializes from. The error
+  /// describes what was expected, for the caller to attach a field name to.
+  pub fn coerce(&self, format: &Format, raw: &str) -> Result<Value, String> {
+    let invalid = |expected: &str| format!("expected {expected}, got {raw:?}");
+
+    match format {
+      Format::Unit => Ok(Value::Null),
+      Format::Bool => match raw {
+        "true" | "yes" | "1" => Ok(Value::Bool(true)),
+        "false" | "no" | "0" => Ok(Value::Bool(false)),
+        _ => Err(invalid("true or false")),
+      },
+      Format::I8 | Format::I16 | Format::I32 | Format::I64 | Format::I128 => raw
+        .parse::<i64>()
+        .map(|n| Value::Number(n.into()))
+        .map_err(|_| invalid("an integer")),
+      Format::U8 | Format::U16 | Format::U32 | Format::U64 | Format::U128 => raw
+        .parse::<u64>()
+        .map(|n| Value::Number(n.into()))
+        .map_err(|_| invalid("a positive integer")),
+      Format::F32 | Format::F64 => raw
+        .parse::<f64>()
+        .ok()
+        .and_then(Number::from_f64)
+        .map(Value::Number)
+        .ok_or_else(|| invalid("a number")),
+      Format::Char | Format::Str | Format::Bytes => Ok(Value::String(raw.to_string())),
+      Format::Option(inner) => self.coerce(inner, raw),
+      Format::Seq(inner) | Format::TupleArray { content: inner, .. } => {
+        if raw.starts_with('[') {
+          return serde_json::from_str(raw).map_err(|_| invalid("a list"));
+        }
+        Ok(Value::Array(vec![self.coerce(inner, raw)?]))
+      }
+      Format::Map { value, .. } => {
+        if raw.starts_with('{') {
+          return serde_json::from_str(raw).map_err(|_| invalid("a json object"));
+        }
+        let (k, v) = raw.split_once('=').ok_or_else(|| invalid("key=value"))?;
+        let mut map = serde_json::Map::new();
+        map.insert(k.to_string(), self.coerce(value, v)?);
+        Ok(Value::Object(map))
+      }
+      Format::Tuple(_) => serde_json::from_str(raw).map_err(|_| invalid("a json value")),
+      Format::TypeName(_) => match self.resolve(format) {
+        Some(ContainerFormat::NewTypeStruct(inner)) => self.coerce(inner, raw),
+        // Unit variants deserialize from their name
+        Some(ContainerFormat::Enum(_)) => {
+          Ok(serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string())))
+        }
+        _ => serde_json::from_str(raw).map_err(|_| invalid("a json value")),
+      },
+      Format::Variable(_) => {
+        Ok(serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string())))
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde::Deserialize;
+  use std::collections::HashMap;
+
+  #[allow(dead_code)]
+  #[derive(Deserialize)]
+  struct Args {
+    id: String,
+    count: i32,
+    ratio: f32,
+    force: bool,
+    note: Option<String>,
+    tags: Vec<String>,
+    from_dump: Option<u32>,
+    labels: HashMap<String, i32>,
+  }
+
+  #[test]
+  fn test_trace_struct_fields() {
+    let schema = ArgSchema::trace::<Args>().expect("traceable");
+    assert!(schema.is_struct());
+    let names: Vec<&str> = schema
+      .fields()
+      .unwrap()
+      .iter()
+      .map(|f| f.name.as_str())
+      .collect();
+    assert_eq!(
+      names,
+      vec![
+        "id",
+        "count",
+        "ratio",
+        "force",
+        "note",
+        "tags",
+        "from_dump",
+        "labels"
+      ]
+    );
+    assert!(schema.field("from-dump").is_some());
+    assert!(schema.field("nope").is_none());
+  }
+
+  #[test]
+  fn test_coerce_by_field_type() {
+    let schema = ArgSchema::trace::<Args>().expect("traceable");
+    let coerce = |flag: &str, raw: &str| schema.coerce(&schema.field(flag).unwrap().value, raw);
+
+    assert_eq!(coerce("id", "1"), Ok(Value::String("1".to_string())));
+    assert_eq!(coerce("count", "1"), Ok(Value::Number(1.into())));
+    assert_eq!(coerce("ratio", "0.5"), Ok(serde_json::json!(0.5)));
+    assert_eq!(coerce("force", "1"), Ok(Value::Bool(true)));
+    assert_eq!(coerce("note", "0"), Ok(Value::String("0".to_string())));
+    assert_eq!(coerce("tags", "a"), Ok(serde_json::json!(["a"])));
+    assert_eq!(coerce("labels", "a=1"), Ok(serde_json::json!({"a": 1})));
+    assert!(coerce("labels", "a").is_err());
+    assert!(coerce("count", "abc").is_err());
+    assert!(coerce("from_dump", "-1").is_err());
+  }
+
+  #[test]
+  fn test_trace_option_root() {
+    let schema = ArgSchema::trace::<Option<Args>>().expect("traceable");
+    assert!(!schema.is_struct());
+    assert!(schema.fields().is_some());
+
+    let schema = ArgSchema::trace::<i32>().expect("traceable");
+    assert!(schema.fields().is_none());
+    assert_eq!(schema.root(), &Format::I32);
+  }
+}
@@ -1,9 +1,139 @@
+use crate::arg_schema::ArgSchema;
+use crate::command_tree::MASK;
+use crate::context::{CancellationToken, CommandContext};
 use crate::error::Error;
+use colored::Colorize;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
 use serde::de;
+use serde_json::Value;
+use serde_reflection::Format;
+use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
+use std::time::Duration;
 use uuid::Uuid;
 
 pub trait CommandArg {
   fn display_help() -> Vec<String>;
+
+  /// Per-field metadata declared with `#[arg(...)]` on the derive.
+  fn args() -> Vec<ArgSpec> {
+    vec![]
+  }
+}
+
+/// Describes one field of a `CommandArg` struct.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArgSpec {
+  pub name: &'static str,
+  pub ty: &'static str,
+  /// Filled from bare words, in declaration order, instead of `--name`.
+  pub positional: bool,
+  /// A trailing positional `Vec<_>` that takes every remaining bare word.
+  pub variadic: bool,
+  /// Single letter form, eg. `-n`. Bool shorts can be bundled as `-rf`.
+  pub short: Option<char>,
+  /// Alternate long names, eg. `--nick` for `--name`.
+  pub aliases: Vec<&'static str>,
+  /// Splits each value of a list or map field, eg. `--tag a,b`.
+  pub delimiter: Option<char>,
+  /// Receives the previous command's result in a pipeline (`a | b`).
+  pub input: bool,
+  /// Masked in history and errors, and prompted for with hidden input when
+  /// it's missing.
+  pub secret: bool,
+}
+
+impl ArgSpec {
+  pub fn new(name: &'static str, ty: &'static str) -> Self {
+    ArgSpec {
+      name,
+      ty,
+      positional: false,
+      variadic: false,
+      short: None,
+      aliases: vec![],
+      delimiter: None,
+      input: false,
+      secret: false,
+    }
+  }
+
+  pub fn positional(mut self) -> Self {
+    self.positional = true;
+    self
+  }
+
+  pub fn variadic(mut self) -> Self {
+    self.positional = true;
+    self.variadic = true;
+    self
+  }
+
+  pub fn short(mut self, short: char) -> Self {
+    self.short = Some(short);
+    self
+  }
+
+  pub fn alias(mut self, alias: &'static str) -> Self {
+    self.aliases.push(alias);
+    self
+  }
+
+  pub fn delimiter(mut self, delimiter: char) -> Self {
+    self.delimiter = Some(delimiter);
+    self
+  }
+
+  pub fn input(mut self) -> Self {
+    self.input = true;
+    self
+  }
+
+  pub fn secret(mut self) -> Self {
+    self.secret = true;
+    self
+  }
+
+  /// True if `flag` (without leading dashes) is this field's name or one of
+  /// its aliases. `-` and `_` are interchangeable.
+  pub fn matches(&self, flag: &str) -> bool {
+    let flag = flag.replace('-', "_");
+    std::iter::once(&self.name)
+      .chain(self.aliases.iter())
+      .any(|name| name.replace('-', "_") == flag)
+  }
+
+  /// How a positional is shown in a usage line, eg. `<FROM>`, `[TO]` or
+  /// `[FILES]...`.
+  pub fn usage(&self) -> String {
+    let name = self.name.to_uppercase();
+    if self.variadic {
+      format!("[{name}]...")
+    } else if self.ty.starts_with("Option<") {
+      format!("[{name}]")
+    } else {
+      format!("<{name}>")
+    }
+  }
+
+  /// The line shown for this field in help, eg. `--name: String`.
+  pub fn help_line(&self) -> String {
+    if self.positional {
+      return format!("{}: {}", self.usage(), self.ty);
+    }
+    let piped = if self.input { " (or piped in)" } else { "" };
+    let secret = if self.secret { " (secret)" } else { "" };
+    format!("{}: {}{piped}{secret}", self.flags().join(", "), self.ty)
+  }
+
+  /// Every spelling of this field's flag, eg. `["-n", "--name", "--nick"]`.
+  pub fn flags(&self) -> Vec<String> {
+    let mut flags: Vec<String> = self.short.map(|c| format!("-{c}")).into_iter().collect();
+    flags.push(format!("--{}", self.name));
+    flags.extend(self.aliases.iter().map(|alias| format!("--{alias}")));
+    flags
+  }
 }
 
 impl CommandArg for String {
@@ -59,9 +189,42 @@ where
     opt.append(help);
     opt
   }
+
+  fn args() -> Vec<ArgSpec> {
+    T::args()
+  }
 }
 
-/// Parse a string value into the appropriate JSON type (bool, i64, or string)
+impl<T> CommandArg for Vec<T>
+where
+  T: CommandArg,
+{
+  fn display_help() -> Vec<String> {
+    T::display_help()
+      .into_iter()
+      .map(|help| format!("{help}..."))
+      .collect()
+  }
+}
+
+impl<K, V> CommandArg for HashMap<K, V>
+where
+  K: CommandArg,
+  V: CommandArg,
+{
+  fn display_help() -> Vec<String> {
+    let key = K::display_help().join(" ");
+    let value = V::display_help().join(" ");
+    vec![format!(
+      "[{}={}]...",
+      key.trim_matches(['[', ']']),
+      value.trim_matches(['[', ']'])
+    )]
+  }
+}
+
+/// Guess the JSON type of a value (bool, i64, or string). Only used when the
+/// argument type couldn't be traced, see `ArgSchema::trace`.
 fn parse_value(arg: &str) -> serde_json::Value {
   if arg == "true" || arg == "1" {
     serde_json::Value::Bool(true)
@@ -74,14 +237,457 @@ fn parse_value(arg: &str) -> serde_json::Value {
   }
 }
 
+/// Converts `raw` to the type of the field matching `key`, returning the
+/// field's serde name with the value. `name` is how the user referred to the
+/// field (`--count`, `<FROM>`) for errors. A missing value is only allowed for
+/// bools.
+fn coerce_value(
+  schema: Option<&ArgSchema>,
+  key: &str,
+  name: &str,
+  raw: Option<&str>,
+) -> crate::Result<(String, serde_json::Value)> {
+  let Some(schema) = schema.filter(|s| s.fields().is_some()) else {
+    // Normalize kebab-case CLI flags (`--from-dump`) to the
+    // snake_case Rust field names serde will deserialize into
+    // (`from_dump`). Without this, fields with `_` in their
+    // names silently parse as missing when the CLI uses `-`.
+    let value = raw.map_or(serde_json::Value::Bool(true), parse_value);
+    return Ok((key.replace('-', "_"), value));
+  };
+
+  let field = schema
+    .field(key)
+    .ok_or_else(|| Error::ArgParseError(format!("unknown option {name}")))?;
+  let value = match raw {
+    Some(raw) => schema
+      .coerce(&field.value, raw)
+      .map_err(|reason| Error::InvalidArgument {
+        name: name.to_string(),
+        reason,
+      })?,
+    None if schema.is_bool(&field.value) => serde_json::Value::Bool(true),
+    None => {
+      return Err(Error::InvalidArgument {
+        name: name.to_string(),
+        reason: "missing value".to_string(),
+      });
+    }
+  };
+  Ok((field.name.clone(), value))
+}
+
+/// Coerces and inserts a flag's value, splitting it on the field's delimiter
+/// first if it has one.
+fn insert_flag(
+  map: &mut serde_json::Map<String, serde_json::Value>,
+  schema: Option<&ArgSchema>,
+  spec: Option<&ArgSpec>,
+  key: &str,
+  raw: Option<&str>,
+) -> crate::Result<()> {
+  let name = format!("--{key}");
+  let parts: Vec<Option<&str>> = match (raw, spec.and_then(|s| s.delimiter)) {
+    (Some(raw), Some(delimiter)) => raw.split(delimiter).map(Some).collect(),
+    _ => vec![raw],
+  };
+  for part in parts {
+    let (field, value) = coerce_value(schema, key, &name, part)?;
+    insert_value(map, field, value);
+  }
+  Ok(())
+}
+
+/// Repeated list and map values accumulate (`--tag a --tag b`), anything else
+/// keeps the last value.
+fn insert_value(
+  map: &mut serde_json::Map<String, serde_json::Value>,
+  field: String,
+  value: serde_json::Value,
+) {
+  use serde_json::Value;
+  match (map.get_mut(&field), value) {
+    (Some(Value::Array(existing)), Value::Array(values)) => existing.extend(values),
+    (Some(Value::Object(existing)), Value::Object(values)) => existing.extend(values),
+    (_, value) => {
+      map.insert(field, value);
+    }
+  }
+}
+
+/// Rewrites short flags (`-n`, bundled `-rf`) and aliases to the field's
+/// `--name`, so the rest of parsing only deals with long flags. Words that
+/// merely start with `-`, like negative numbers, are left alone.
+fn expand_flags(argv: Vec<String>, specs: &[ArgSpec]) -> crate::Result<Vec<String>> {
+  let mut expanded = Vec::with_capacity(argv.len());
+  for (i, arg) in argv.into_iter().enumerate() {
+    if i == 0 {
+      expanded.push(arg);
+      continue;
+    }
+
+    if let Some(flag) = arg.strip_prefix("--") {
+      let (name, value) = match flag.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (flag, None),
+      };
+      match specs.iter().find(|spec| spec.matches(name)) {
+        Some(spec) if spec.name != name => expanded.push(match value {
+          Some(value) => format!("--{}={value}", spec.name),
+          None => format!("--{}", spec.name),
+        }),
+        _ => expanded.push(arg),
+      }
+      continue;
+    }
+
+    let find_short = |c: char| specs.iter().find(|spec| spec.short == Some(c));
+    let is_short = arg.len() > 1
+      && arg.starts_with('-')
+      && arg[1..].chars().next().and_then(find_short).is_some();
+    if !is_short {
+      expanded.push(arg);
+      continue;
+    }
+
+    let (bundle, value) = match arg[1..].split_once('=') {
+      Some((bundle, value)) => (bundle, Some(value)),
+      None => (&arg[1..], None),
+    };
+    let count = bundle.chars().count();
+    for (j, c) in bundle.chars().enumerate() {
+      let spec =
+        find_short(c).ok_or_else(|| Error::ArgParseError(format!("unknown option -{c}")))?;
+      match value {
+        Some(value) if j + 1 == count => expanded.push(format!("--{}={value}", spec.name)),
+        _ => expanded.push(format!("--{}", spec.name)),
+      }
+    }
+  }
+  Ok(expanded)
+}
+
+/// Hands the previous command's result to this one: to the `#[arg(input)]`
+/// field if there is one, otherwise by merging an object's keys into the
+/// flags. Flags given on the command line win.
+fn pipe_input(
+  map: &mut serde_json::Map<String, serde_json::Value>,
+  schema: Option<&ArgSchema>,
+  specs: &[ArgSpec],
+  input: serde_json::Value,
+) -> crate::Result<()> {
+  use serde_json::Value;
+  if let Some(spec) = specs.iter().find(|s| s.input) {
+    let field = schema
+      .and_then(|s| s.field(spec.name))
+      .map_or_else(|| spec.name.to_string(), |f| f.name.clone());
+    map.entry(field).or_insert(input);
+    return Ok(());
+  }
+
+  match input {
+    Value::Object(object) => {
+      for (key, value) in object {
+        // Keys the command doesn't know about are dropped
+        let field = match schema.filter(|s| s.fields().is_some()) {
+          Some(schema) => match schema.field(&key) {
+            Some(field) => field.name.clone(),
+            None => continue,
+          },
+          None => key,
+        };
+        map.entry(field).or_insert(value);
+      }
+      Ok(())
+    }
+    _ => Err(Error::ArgParseError(
+      "this command can't take piped input, it has no #[arg(input)] field".to_string(),
+    )),
+  }
+}
+
+/// Fails naming the first required secret field that wasn't given, so the
+/// error doesn't come from serde (or get prompted for) with the other fields.
+fn require_secrets(
+  map: &serde_json::Map<String, serde_json::Value>,
+  schema: Option<&ArgSchema>,
+  specs: &[ArgSpec],
+) -> crate::Result<()> {
+  let missing = specs
+    .iter()
+    .filter(|s| s.secret && !s.ty.starts_with("Option<"))
+    .find(|spec| !map.contains_key(&field_name(schema, spec)));
+  match missing {
+    Some(spec) => Err(Error::InvalidArgument {
+      name: format!("--{}", spec.name),
+      reason: "a value is required".to_string(),
+    }),
+    None => Ok(()),
+  }
+}
+
+/// The serde name of the field `spec` describes.
+fn field_name(schema: Option<&ArgSchema>, spec: &ArgSpec) -> String {
+  schema
+    .and_then(|s| s.field(spec.name))
+    .map_or_else(|| spec.name.to_string(), |f| f.name.clone())
+}
+
+/// Asks for one field by name and type until the answer is valid. An empty
+/// answer gives up, Ctrl-C cancels the command.
+async fn prompt_for(schema: Option<&ArgSchema>, spec: &ArgSpec) -> crate::Result<(String, Value)> {
+  let name = if spec.positional {
+    spec.usage()
+  } else {
+    format!("--{}", spec.name)
+  };
+  loop {
+    let question = format!("{name} ({}): ", spec.ty);
+    let answer = read_answer(question, spec.secret)
+      .await
+      .map_err(|e| Error::Other(anyhow::Error::new(e).context(format!("couldn't read {name}"))))?
+      .ok_or(Error::Cancelled)?;
+    if answer.is_empty() {
+      return Err(Error::ArgParseError(format!("missing {name}")));
+    }
+    match coerce_value(schema, spec.name, &name, Some(&answer)) {
+      // Don't echo a secret back
+      Err(Error::InvalidArgument { .. }) if spec.secret => {
+        eprintln!("{}", format!("not a valid {}", spec.ty).yellow())
+      }
+      Err(Error::InvalidArgument { reason, .. }) => eprintln!("{}", reason.yellow()),
+      result => return result,
+    }
+  }
+}
+
+/// Reads an answer on a blocking thread, so the command's task isn't held
+/// up. The terminal is in raw mode meanwhile, which makes Ctrl-C a key press
+/// rather than a signal. It and the command being cancelled both return
+/// `None`.
+async fn read_answer(question: String, hidden: bool) -> std::io::Result<Option<String>> {
+  let token = CommandContext::current().cancellation_token();
+  let read = tokio::task::spawn_blocking(move || {
+    print!("{question}");
+    std::io::stdout().flush()?;
+    terminal::enable_raw_mode()?;
+    let answer = read_keys(&token, hidden);
+    terminal::disable_raw_mode()?;
+    println!();
+    answer
+  });
+  read.await.map_err(std::io::Error::other)?
+}
+
+/// Reads keys up to enter, echoing them unless `hidden`. Ctrl-D on an empty
+/// answer gives up like an empty line.
+fn read_keys(token: &CancellationToken, hidden: bool) -> std::io::Result<Option<String>> {
+  let echo = |text: &str| {
+    if !hidden {
+      print!("{text}");
+      let _ = std::io::stdout().flush();
+    }
+  };
+  let mut answer = String::new();
+  // Polled so the thread ends soon after the command is cancelled
+  while !token.is_cancelled() {
+    if !event::poll(Duration::from_millis(50))? {
+      continue;
+    }
+    let Event::Key(key) = event::read()? else {
+      continue;
+    };
+    if key.kind == KeyEventKind::Release {
+      continue;
+    }
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    match key.code {
+      KeyCode::Enter => return Ok(Some(answer)),
+      KeyCode::Char('c') if ctrl => return Ok(None),
+      KeyCode::Char('d') if ctrl && answer.is_empty() => return Ok(Some(answer)),
+      KeyCode::Char(c) if !ctrl => {
+        answer.push(c);
+        echo(c.encode_utf8(&mut [0; 4]));
+      }
+      KeyCode::Backspace if answer.pop().is_some() => echo("\u{8} \u{8}"),
+      _ => {}
+    }
+  }
+  Ok(None)
+}
+
+/// The values given to secret flags in `argv`, so errors can leave them out.
+fn secret_values(argv: &[String], specs: &[ArgSpec]) -> Vec<String> {
+  let flags: Vec<String> = specs
+    .iter()
+    .filter(|s| s.secret)
+    .flat_map(ArgSpec::flags)
+    .collect();
+  let mut values = vec![];
+  let mut args = argv.iter().skip(1);
+  while let Some(arg) = args.next() {
+    match arg.split_once('=') {
+      Some((flag, value)) if flags.iter().any(|f| f == flag) => values.push(value.to_string()),
+      None if flags.contains(arg) => values.extend(args.next().cloned()),
+      _ => {}
+    }
+  }
+  values.retain(|value| !value.is_empty());
+  values
+}
+
+fn redact_error(e: Error, secrets: &[String]) -> Error {
+  let redact = |text: String| {
+    secrets
+      .iter()
+      .fold(text, |text, secret| text.replace(secret.as_str(), MASK))
+  };
+  match e {
+    Error::ArgParseError(message) => Error::ArgParseError(redact(message)),
+    Error::InvalidArgument { name, reason } => Error::InvalidArgument {
+      name,
+      reason: redact(reason),
+    },
+    Error::Other(e) if !secrets.is_empty() => {
+      Error::Other(anyhow::anyhow!(redact(format!("{e:#}"))))
+    }
+    e => e,
+  }
+}
+
+/// Parses a command's argv into its arguments. Missing required arguments
+/// are an error, see `parse_arguments_prompting` to ask for them instead.
 pub fn parse_arguments<T>(argv: Vec<String>) -> crate::Result<T>
+where
+  T: de::DeserializeOwned + CommandArg,
+{
+  let secrets = secret_values(&argv, &T::args());
+  let result = match parse(argv, false) {
+    Ok(Parsed::Args(args)) => Ok(args),
+    Ok(Parsed::Fields(fields)) => fields.deserialize(),
+    Err(e) => Err(e),
+  };
+  result.map_err(|e| redact_error(e, &secrets))
+}
+
+/// Like `parse_arguments`, but where the shell allows it (the REPL, or
+/// `--prompt`) asks for missing required arguments instead of failing.
+pub async fn parse_arguments_prompting<T>(argv: Vec<String>) -> crate::Result<T>
+where
+  T: de::DeserializeOwned + CommandArg,
+{
+  let secrets = secret_values(&argv, &T::args());
+  let prompt = std::io::stdin().is_terminal() && CommandContext::current().prompts_for_missing();
+  let result = async {
+    let mut fields = match parse(argv, prompt)? {
+      Parsed::Args(args) => return Ok(args),
+      Parsed::Fields(fields) => fields,
+    };
+    loop {
+      let e = match fields.deserialize() {
+        Ok(args) => return Ok(args),
+        Err(e) => e,
+      };
+      let Some(spec) = fields.missing().filter(|_| prompt).cloned() else {
+        return Err(e);
+      };
+      let (field, value) = prompt_for(fields.schema.as_ref(), &spec).await?;
+      fields.map.insert(field, value);
+    }
+  };
+  result.await.map_err(|e| redact_error(e, &secrets))
+}
+
+/// What `parse` read from argv: the arguments themselves when there was no
+/// need for a map of fields, eg. for a primitive.
+enum Parsed<T> {
+  Args(T),
+  Fields(Fields),
+}
+
+/// Flags, positionals and piped input by field, before they're deserialized.
+struct Fields {
+  map: serde_json::Map<String, Value>,
+  schema: Option<ArgSchema>,
+  specs: Vec<ArgSpec>,
+}
+
+impl Fields {
+  fn deserialize<T>(&self) -> crate::Result<T>
+  where
+    T: de::DeserializeOwned + CommandArg,
+  {
+    let ser = serde_json::to_string(&self.map).map_err(|e| Error::Other(e.into()))?;
+    let e = match serde_json::from_str(&ser) {
+      Ok(args) => return Ok(args),
+      Err(e) => e,
+    };
+
+    let expected_fields = T::display_help();
+    let provided_fields: Vec<String> = self.map.keys().map(|k| format!("--{}", k)).collect();
+
+    Err(if expected_fields.is_empty() {
+      Error::Other(e.into())
+    } else {
+      let expected_list = expected_fields.join(", ");
+      let provided_list = provided_fields.join(", ");
+      Error::ArgParseError(format!(
+        "Failed to parse arguments.\nExpected: {}\nProvided: {}\nError: {}",
+        expected_list, provided_list, e
+      ))
+    })
+  }
+
+  /// The first required field, in declaration order, that has no value.
+  fn missing(&self) -> Option<&ArgSpec> {
+    self.specs.iter().find(|spec| {
+      !spec.variadic
+        && !spec.ty.starts_with("Option<")
+        && !self
+          .map
+          .contains_key(&field_name(self.schema.as_ref(), spec))
+    })
+  }
+}
+
+/// Reads argv (and piped input) into fields. `prompt` is whether missing
+/// arguments will be asked for, rather than reported.
+fn parse<T>(argv: Vec<String>, prompt: bool) -> crate::Result<Parsed<T>>
 where
   T: de::DeserializeOwned + CommandArg,
 {
   // println!("--argv {:#?}", argv);
 
-  if argv.len() == 1 {
-    return Ok(serde_json::from_str("null").map_err(|_| {
+  let schema = ArgSchema::trace::<T>();
+  // A command that returned nothing pipes nothing
+  let input = CommandContext::current()
+    .input()
+    .filter(|input| !input.is_null())
+    .cloned();
+  let takes_fields = schema.as_ref().is_some_and(|s| s.fields().is_some());
+
+  if let Some(input) = &input {
+    // Without flags, piped input is the whole argument, eg. `count | double`
+    if argv.len() == 1 && !takes_fields {
+      return serde_json::from_value(input.clone())
+        .map(Parsed::Args)
+        .map_err(|e| Error::ArgParseError(format!("piped input doesn't fit this command: {e}")));
+    }
+  }
+
+  let specs = T::args();
+  // Missing arguments are asked for when the shell allows it (the REPL, or
+  // `--prompt`), otherwise a missing secret is reported without its value
+  let has_secrets = specs.iter().any(|s| s.secret);
+
+  if argv.len() == 1 && input.is_none() && !has_secrets && !(prompt && !specs.is_empty()) {
+    // A struct whose fields are all optional can be called without flags
+    let empty = if schema.as_ref().is_some_and(|s| s.is_struct()) {
+      "{}"
+    } else {
+      "null"
+    };
+    return serde_json::from_str(empty).map(Parsed::Args).map_err(|_| {
       let expected_fields = T::display_help();
       if expected_fields.is_empty() {
         Error::ArgParseError("No arguments expected, but command failed".to_string())
@@ -89,78 +695,144 @@ where
         let fields_list = expected_fields.join(", ");
         Error::ArgParseError(format!("Missing required arguments: {}", fields_list))
       }
-    })?);
+    });
   }
 
-  if argv.len() == 2 {
+  let argv = expand_flags(argv, &specs)?;
+  let positionals: Vec<ArgSpec> = specs.iter().filter(|a| a.positional).cloned().collect();
+
+  if argv.len() == 2 && positionals.is_empty() {
     let only = argv.get(1).expect("");
 
     // If it's a flag (starts with --), fall through to the map parsing logic
     if !only.starts_with("--") {
       // Deserializing directly implies primitive (i32, i64, etc)
       //
-      if let Ok(only) = serde_json::from_str(&only) {
-        return Ok(only);
+      if let Some(schema) = schema.as_ref().filter(|s| s.fields().is_none()) {
+        let value =
+          schema
+            .coerce(schema.root(), only)
+            .map_err(|reason| Error::InvalidArgument {
+              name: "argument".to_string(),
+              reason,
+            })?;
+        return serde_json::from_value(value)
+          .map(Parsed::Args)
+          .map_err(|e| Error::ArgParseError(e.to_string()));
+      }
+
+      if let Ok(only) = serde_json::from_str(only) {
+        return Ok(Parsed::Args(only));
       };
 
       let ser = serde_json::to_string(&only).map_err(|e| Error::Other(e.into()))?;
-      return Ok(serde_json::from_str(&ser).map_err(|e| Error::Other(e.into()))?);
+      return Ok(Parsed::Args(
+        serde_json::from_str(&ser).map_err(|e| Error::Other(e.into()))?,
+      ));
     }
   }
 
   use serde_json::Map;
   let mut map: Map<String, serde_json::Value> = Map::new();
-  let mut key: Option<String> = None;
-  for (i, arg) in argv.iter().enumerate() {
+  let takes_positionals = !positionals.is_empty();
+  let mut positionals = positionals.iter();
+  let mut variadic: Option<&ArgSpec> = None;
+  let mut args = argv.iter().skip(1).peekable();
+  while let Some(arg) = args.next() {
     // println!("[]: {arg}");
-    if i == 0 {
-      continue;
-    }
-
-    if arg.starts_with("--") {
-      // Normalize kebab-case CLI flags (`--from-dump`) to the
-      // snake_case Rust field names serde will deserialize into
-      // (`from_dump`). Without this, fields with `_` in their
-      // names silently parse as missing when the CLI uses `-`.
-      let arg = arg.trim_start_matches("--").replace('-', "_");
-      // If there's a pending key without a value, treat it as a boolean flag
-      if let Some(pending_key) = key.take() {
-        map.insert(pending_key, serde_json::Value::Bool(true));
-      }
+    if let Some(flag) = arg.strip_prefix("--") {
       // Check for --key=value syntax
-      if let Some((k, v)) = arg.split_once('=') {
-        let value = parse_value(v);
-        map.insert(k.to_owned(), value);
-      } else {
-        key = Some(arg);
+      if let Some((k, v)) = flag.split_once('=') {
+        let spec = specs.iter().find(|s| s.name == k);
+        insert_flag(&mut map, schema.as_ref(), spec, k, Some(v))?;
+        continue;
       }
+
+      // A bool flag only takes the next word if it's one of the spellings
+      // `coerce` accepts, so `--force file.txt` leaves `file.txt` for a
+      // positional
+      let takes = |next: &str| match schema.as_ref() {
+        Some(s) if s.field(flag).is_some_and(|f| s.is_bool(&f.value)) => {
+          s.coerce(&Format::Bool, next).is_ok()
+        }
+        _ => true,
+      };
+      let raw = match args.peek() {
+        Some(next) if !next.starts_with("--") && takes(next) => args.next(),
+        // If there's no value, treat it as a boolean flag
+        _ => None,
+      };
+      let spec = specs.iter().find(|s| s.name == flag);
+      insert_flag(
+        &mut map,
+        schema.as_ref(),
+        spec,
+        flag,
+        raw.map(String::as_str),
+      )?;
     } else {
-      let Some(unwrapped_key) = key else {
-        return Err(Error::ArgParseError("param without option".to_string()));
+      let spec = match variadic.or_else(|| positionals.next()) {
+        Some(spec) => spec,
+        None if !takes_positionals => {
+          return Err(Error::ArgParseError("param without option".to_string()));
+        }
+        None => return Err(Error::ArgParseError(format!("unexpected argument {arg}"))),
+      };
+      let (field, value) = coerce_value(schema.as_ref(), spec.name, &spec.usage(), Some(arg))?;
+      if !spec.variadic {
+        map.insert(field, value);
+        continue;
+      }
+
+      variadic = Some(spec);
+      let values = match value {
+        serde_json::Value::Array(values) => values,
+        value => vec![value],
       };
-      let value = parse_value(arg);
-      map.insert(unwrapped_key, value);
-      key = None;
+      insert_value(&mut map, field, serde_json::Value::Array(values));
     }
   }
-  // If there's a trailing key without a value, treat it as a boolean flag
-  if let Some(pending_key) = key {
-    map.insert(pending_key, serde_json::Value::Bool(true));
+  // A variadic positional that got no words is an empty list
+  for spec in positionals.filter(|spec| spec.variadic) {
+    let field = schema
+      .as_ref()
+      .and_then(|s| s.field(spec.name))
+      .map_or_else(|| spec.name.to_string(), |f| f.name.clone());
+    map
+      .entry(field)
+      .or_insert_with(|| serde_json::Value::Array(vec![]));
   }
-  let ser = serde_json::to_string(&map).map_err(|e| Error::Other(e.into()))?;
-  Ok(serde_json::from_str(&ser).map_err(|e| {
-    let expected_fields = T::display_help();
-    let provided_fields: Vec<String> = map.keys().map(|k| format!("--{}", k)).collect();
+  if let Some(input) = input {
+    pipe_input(&mut map, schema.as_ref(), &specs, input)?;
+  }
+  if has_secrets && !prompt {
+    require_secrets(&map, schema.as_ref(), &specs)?;
+  }
+  Ok(Parsed::Fields(Fields { map, schema, specs }))
+}
 
-    if expected_fields.is_empty() {
-      Error::Other(e.into())
-    } else {
-      let expected_list = expected_fields.join(", ");
-      let provided_list = provided_fields.join(", ");
-      Error::ArgParseError(format!(
-        "Failed to parse arguments.\nExpected: {}\nProvided: {}\nError: {}",
-        expected_list, provided_list, e
-      ))
-    }
-  })?)
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[test]
+  fn test_missing_follows_declared_fields() {
+    let fields = |map: Value| Fields {
+      map: map.as_object().cloned().unwrap(),
+      schema: None,
+      specs: vec![
+        ArgSpec::new("name", "String"),
+        ArgSpec::new("force", "Option<bool>"),
+        ArgSpec::new("token", "String").secret(),
+        ArgSpec::new("files", "Vec<String>").variadic(),
+      ],
+    };
+    let missing = |map: Value| fields(map).missing().map(|spec| spec.name);
+
+    assert_eq!(missing(json!({})), Some("name"));
+    // Optional and variadic fields are never missing
+    assert_eq!(missing(json!({"name": "ana"})), Some("token"));
+    assert_eq!(missing(json!({"name": "ana", "token": "abc"})), None);
+  }
 }
@@ -2,7 +2,9 @@ use std::collections::HashMap;
 
 use colored::Colorize;
 
+use crate::ArgSpec;
 use crate::command_metadata::CommandMetadata;
+use crate::command_tree::CommandTree;
 
 pub struct CommandList<Info> {
   commands: HashMap<String, Box<dyn CommandMetadata<Info>>>,
@@ -18,7 +20,7 @@ where
     }
   }
 
-  fn print_indent(&self, level: usize) {
+  pub(crate) fn print_indent(&self, level: usize) {
     print!("{}", " ".repeat(4 + (level) * 5));
   }
 
@@ -65,4 +67,19 @@ where
     keys.sort();
     keys
   }
+
+  pub fn command_tree(&self) -> CommandTree {
+    let mut tree = CommandTree::new();
+    for command in self.commands.values() {
+      tree.add_command(command.name(), command.description(), &command.help());
+      let secrets = command
+        .args()
+        .iter()
+        .filter(|arg| arg.secret)
+        .flat_map(ArgSpec::flags)
+        .collect();
+      tree.set_secrets(command.name(), secrets);
+    }
+    tree
+  }
 }
@@ -1,4 +1,4 @@
-use crate::{CommandArg, error::Error};
+use crate::{ArgSpec, CommandArg, error::Error};
 use colored::Colorize;
 use futures::Future;
 use serde_json::Value;
@@ -22,21 +22,36 @@ pub trait CommandMetadata<Info> {
     argv: Vec<String>,
   ) -> Result<std::pin::Pin<Box<dyn Future<Output = Result<Value, Error>>>>, Error>;
   fn help(&self) -> Vec<String>;
+  fn args(&self) -> Vec<ArgSpec> {
+    vec![]
+  }
 
   fn print_help(&self) {
-    let options = self.help();
+    let positionals: Vec<ArgSpec> = self.args().into_iter().filter(|a| a.positional).collect();
+    let arguments: Vec<String> = positionals.iter().map(ArgSpec::help_line).collect();
+    let options: Vec<String> = self
+      .help()
+      .into_iter()
+      .filter(|h| !arguments.contains(h))
+      .collect();
 
-    println!(
-      "\n{}\n    {} {}",
-      "Name:".bold(),
-      self.name().bold(),
-      if options.len() > 0 { "[OPTIONS]" } else { "" }
-    );
+    let mut usage = vec![self.name().bold().to_string()];
+    usage.extend(positionals.iter().map(ArgSpec::usage));
+    if options.len() > 0 {
+      usage.push("[OPTIONS]".to_string());
+    }
+    println!("\n{}\n    {}", "Name:".bold(), usage.join(" "));
     println!(
       "\n{}\n    {}\n",
       "Description:".bold(),
       self.long_description().unwrap_or(self.description())
     );
+    if !arguments.is_empty() {
+      println!("{}", "Arguments:".bold());
+      for argument in arguments {
+        println!("    {}", argument);
+      }
+    }
     if options.len() > 0 {
       println!("{}", "Options:".bold());
       for option in options {
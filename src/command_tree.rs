@@ -0,0 +1,245 @@
+use std::collections::BTreeMap;
+
+use std::ops::Range;
+
+use crate::tokenizer::{self, Token};
+
+/// What secret values are replaced with in history.
+pub(crate) const MASK: &str = "***";
+
+/// A snapshot of every command, namespace and flag registered on a shell.
+///
+/// `Scripts` and `CommandList` hold trait objects that can't be shared with
+/// reedline (which requires `Send`), so line editing features work off this
+/// plain copy instead.
+#[derive(Clone, Debug, Default)]
+pub struct CommandTree {
+  nodes: BTreeMap<String, CommandNode>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CommandNode {
+  pub description: String,
+  /// Every spelling of each flag, grouped by field, eg. `["-n", "--name"]`.
+  pub flags: Vec<Vec<String>>,
+  /// Spellings of the flags marked `#[arg(secret)]`.
+  pub secrets: Vec<String>,
+  /// Present when this node is a namespace rather than a command.
+  pub children: Option<CommandTree>,
+}
+
+impl CommandTree {
+  pub fn new() -> Self {
+    CommandTree {
+      nodes: BTreeMap::new(),
+    }
+  }
+
+  pub fn add_command(&mut self, name: &str, description: &str, help: &[String]) {
+    self.nodes.insert(
+      name.to_string(),
+      CommandNode {
+        description: description.to_string(),
+        flags: flags_from_help(help),
+        secrets: vec![],
+        children: None,
+      },
+    );
+  }
+
+  pub fn add_namespace(&mut self, name: &str, children: CommandTree) {
+    self.nodes.insert(
+      name.to_string(),
+      CommandNode {
+        description: String::new(),
+        flags: vec![],
+        secrets: vec![],
+        children: Some(children),
+      },
+    );
+  }
+
+  /// Marks flags of the command `name` whose values shouldn't be kept.
+  pub fn set_secrets(&mut self, name: &str, secrets: Vec<String>) {
+    if let Some(node) = self.nodes.get_mut(name) {
+      node.secrets = secrets;
+    }
+  }
+
+  pub fn get(&self, name: &str) -> Option<&CommandNode> {
+    self.nodes.get(name)
+  }
+
+  /// Masks the values given to secret flags, eg. `login --token abc` becomes
+  /// `login --token ***`. Only the word after a secret flag (or after its
+  /// `=`) is replaced, wherever else the same text appears in the line.
+  pub fn redact(&self, line: &str) -> String {
+    let Ok(tokens) = tokenizer::spans(line) else {
+      return line.to_string();
+    };
+    let mut masks = vec![];
+    for command in tokens.split(|(token, _)| matches!(token, Token::Operator(_))) {
+      let words: Vec<(String, Range<usize>)> = command
+        .iter()
+        .filter_map(|(token, span)| match token {
+          Token::Word(word) => Some((word.clone(), span.clone())),
+          Token::Operator(_) => None,
+        })
+        .collect();
+      masks.extend(self.secret_spans(&words));
+    }
+
+    let mut redacted = line.to_string();
+    for (span, replacement) in masks.into_iter().rev() {
+      redacted.replace_range(span, &replacement);
+    }
+    redacted
+  }
+
+  /// Where the values given to secret flags are in one command, with what
+  /// to replace them with.
+  fn secret_spans(&self, words: &[(String, Range<usize>)]) -> Vec<(Range<usize>, String)> {
+    let mut tree = self;
+    let mut words = words.iter();
+    let node = loop {
+      let Some(node) = words.next().and_then(|(word, _)| tree.nodes.get(word)) else {
+        return vec![];
+      };
+      match &node.children {
+        Some(children) => tree = children,
+        None => break node,
+      }
+    };
+
+    let is_secret = |flag: &str| node.secrets.iter().any(|s| s == flag);
+    let mut spans = vec![];
+    while let Some((word, span)) = words.next() {
+      match word.split_once('=') {
+        Some((flag, value)) if is_secret(flag) && !value.is_empty() => {
+          spans.push((span.clone(), format!("{flag}={MASK}")))
+        }
+        None if is_secret(word) => {
+          if let Some((_, span)) = words.next().filter(|(value, _)| !value.is_empty()) {
+            spans.push((span.clone(), MASK.to_string()));
+          }
+        }
+        _ => {}
+      }
+    }
+    spans
+  }
+
+  /// Returns the `(candidate, description)` pairs that can follow `words`
+  /// and start with `partial`.
+  pub fn complete(&self, words: &[String], partial: &str) -> Vec<(String, String)> {
+    let mut tree = self;
+    let mut words = words.iter();
+    loop {
+      let Some(word) = words.next() else {
+        return tree
+          .nodes
+          .iter()
+          .filter(|(name, _)| name.starts_with(partial))
+          .map(|(name, node)| (name.clone(), node.description.clone()))
+          .collect();
+      };
+      let Some(node) = tree.nodes.get(word) else {
+        return vec![];
+      };
+      match &node.children {
+        Some(children) => tree = children,
+        None => {
+          if !partial.starts_with('-') {
+            return vec![];
+          }
+          let used: Vec<&str> = words
+            .map(|word| word.split('=').next().unwrap_or(word))
+            .collect();
+          return node
+            .flags
+            .iter()
+            .filter(|spellings| !spellings.iter().any(|f| used.contains(&f.as_str())))
+            .flatten()
+            .filter(|flag| flag.starts_with(partial))
+            .map(|flag| (flag.clone(), String::new()))
+            .collect();
+        }
+      }
+    }
+  }
+}
+
+/// Pulls the flag names out of the strings produced by
+/// `CommandArg::display_help`, eg. `-e, --environment, --env: String`.
+fn flags_from_help(help: &[String]) -> Vec<Vec<String>> {
+  help
+    .iter()
+    .filter(|line| line.starts_with('-'))
+    .filter_map(|line| line.split(':').next())
+    .map(|flags| flags.split(", ").map(|f| f.trim().to_string()).collect())
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn tree() -> CommandTree {
+    let mut auth = CommandTree::new();
+    auth.add_command(
+      "login",
+      "Logs in",
+      &["-t, --token: String (secret)".to_string()],
+    );
+    auth.set_secrets("login", vec!["-t".to_string(), "--token".to_string()]);
+    let mut tree = CommandTree::new();
+    tree.add_namespace("auth", auth);
+    tree
+  }
+
+  #[test]
+  fn test_redact_secret_flags() {
+    let tree = tree();
+    assert_eq!(
+      tree.redact("auth login --token abc --user ana"),
+      "auth login --token *** --user ana"
+    );
+    assert_eq!(tree.redact("auth login -t=abc"), "auth login -t=***");
+    assert_eq!(
+      tree.redact("status | auth login --token 'a b'"),
+      "status | auth login --token ***"
+    );
+    assert_eq!(tree.redact("echo --token abc"), "echo --token abc");
+  }
+
+  #[test]
+  fn test_redact_escaped_secret() {
+    assert_eq!(
+      tree().redact(r"auth login --token a\ b --user ana"),
+      "auth login --token *** --user ana"
+    );
+  }
+
+  #[test]
+  fn test_redact_only_the_secret_word() {
+    let tree = tree();
+    // The same text elsewhere in the line is left alone
+    assert_eq!(
+      tree.redact("auth login --token o"),
+      "auth login --token ***"
+    );
+    assert_eq!(
+      tree.redact("auth login --user a --token a"),
+      "auth login --user a --token ***"
+    );
+    // `-t` inside `--tag` isn't the secret flag
+    assert_eq!(
+      tree.redact(r"auth login --tag v -t a\ b"),
+      "auth login --tag v -t ***"
+    );
+    assert_eq!(
+      tree.redact("auth login --tag=x -t='a b' && auth login -t 'é' ;"),
+      "auth login --tag=x -t=*** && auth login -t *** ;"
+    );
+  }
+}
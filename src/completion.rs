@@ -0,0 +1,152 @@
+use std::sync::{Arc, RwLock};
+
+use reedline::{Completer, Span, Suggestion};
+
+use crate::command_tree::CommandTree;
+use crate::tokenizer::{IntoArgs, Token};
+
+/// Completes command names, namespaces and `--flags` from the shell's
+/// registered commands.
+#[derive(Clone)]
+pub struct ShellCompleter {
+  commands: Arc<RwLock<CommandTree>>,
+}
+
+impl ShellCompleter {
+  pub fn new(commands: Arc<RwLock<CommandTree>>) -> Self {
+    ShellCompleter { commands }
+  }
+}
+
+impl Completer for ShellCompleter {
+  fn complete(&mut self, line: &str, pos: usize) -> Vec<Suggestion> {
+    let line = &line[..pos.min(line.len())];
+    let start = line.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+    let partial = &line[start..];
+
+    // An unfinished quote earlier in the line shouldn't disable completion
+    let tokens = (&line[..start]).try_into_tokens().unwrap_or_else(|_| {
+      line[..start]
+        .split_whitespace()
+        .map(|s| Token::Word(s.to_string()))
+        .collect()
+    });
+    // Only the command after the last `|`, `;`, `&&`, ... matters
+    let words: Vec<String> = tokens
+      .into_iter()
+      .rev()
+      .map_while(|token| match token {
+        Token::Word(word) => Some(word),
+        Token::Operator(_) => None,
+      })
+      .collect::<Vec<_>>()
+      .into_iter()
+      .rev()
+      .collect();
+
+    let commands = self.commands.read().expect("");
+    commands
+      .complete(&words, partial)
+      .into_iter()
+      .map(|(value, description)| Suggestion {
+        value,
+        description: if description.is_empty() {
+          None
+        } else {
+          Some(description)
+        },
+        style: None,
+        extra: None,
+        span: Span::new(start, line.len()),
+        append_whitespace: true,
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn completer() -> ShellCompleter {
+    let mut status = CommandTree::new();
+    status.add_command("print", "Prints the status", &[]);
+    status.add_command("log", "Logs the status", &[]);
+
+    let mut tree = CommandTree::new();
+    tree.add_command(
+      "deploy",
+      "Deploys the app",
+      &[
+        "-e, --environment, --env: String".to_string(),
+        "--dry_run: Option<bool>".to_string(),
+      ],
+    );
+    tree.add_command("describe", "Describes the app", &[]);
+    tree.add_namespace("status", status);
+    ShellCompleter::new(Arc::new(RwLock::new(tree)))
+  }
+
+  fn values(suggestions: Vec<Suggestion>) -> Vec<String> {
+    suggestions.into_iter().map(|s| s.value).collect()
+  }
+
+  #[test]
+  fn test_complete_root_commands() {
+    let mut completer = completer();
+    assert_eq!(
+      values(completer.complete("de", 2)),
+      vec!["deploy", "describe"]
+    );
+    assert_eq!(
+      values(completer.complete("", 0)),
+      vec!["deploy", "describe", "status"]
+    );
+  }
+
+  #[test]
+  fn test_complete_namespace_commands() {
+    let mut completer = completer();
+    let suggestions = completer.complete("status pr", 9);
+    assert_eq!(suggestions.len(), 1);
+    assert_eq!(suggestions[0].value, "print");
+    assert_eq!(suggestions[0].span, Span::new(7, 9));
+  }
+
+  #[test]
+  fn test_complete_after_pipe() {
+    let mut completer = completer();
+    assert_eq!(
+      values(completer.complete("status print | de", 17)),
+      vec!["deploy", "describe"]
+    );
+    assert_eq!(
+      values(completer.complete("describe | deploy --d", 21)),
+      vec!["--dry_run"]
+    );
+  }
+
+  #[test]
+  fn test_complete_flags() {
+    let mut completer = completer();
+    let suggestions = completer.complete("deploy --en", 11);
+    assert_eq!(values(suggestions), vec!["--environment", "--env"]);
+    assert_eq!(
+      values(completer.complete("deploy -", 8)),
+      vec!["-e", "--environment", "--env", "--dry_run"]
+    );
+
+    // flags that were already typed aren't offered again
+    assert_eq!(
+      values(completer.complete("deploy --environment prod --", 28)),
+      vec!["--dry_run"]
+    );
+  }
+
+  #[test]
+  fn test_complete_unknown_command() {
+    let mut completer = completer();
+    assert!(completer.complete("nope --", 7).is_empty());
+    assert!(completer.complete("deploy pr", 9).is_empty());
+  }
+}
@@ -0,0 +1,110 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use serde_json::Value;
+use tokio::sync::watch;
+
+tokio::task_local! {
+  static CONTEXT: CommandContext;
+}
+
+/// Signals that the user asked for the running command to stop (Ctrl-C).
+#[derive(Clone, Debug)]
+pub struct CancellationToken {
+  cancelled: Arc<watch::Sender<bool>>,
+}
+
+impl CancellationToken {
+  pub fn new() -> Self {
+    CancellationToken {
+      cancelled: Arc::new(watch::Sender::new(false)),
+    }
+  }
+
+  pub fn cancel(&self) {
+    self.cancelled.send_replace(true);
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    *self.cancelled.borrow()
+  }
+
+  /// Resolves once the token is cancelled.
+  pub async fn cancelled(&self) {
+    let mut rx = self.cancelled.subscribe();
+    // The sender lives in `self`, so this can't fail
+    let _ = rx.wait_for(|cancelled| *cancelled).await;
+  }
+}
+
+impl Default for CancellationToken {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// State for the command that's currently running, available from inside
+/// the command with `CommandContext::current()`.
+///
+/// ```ignore
+/// let ctx = mysh::CommandContext::current();
+/// while !ctx.is_cancelled() {
+///   // ...
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CommandContext {
+  cancellation: CancellationToken,
+  input: Option<Value>,
+  prompt: bool,
+}
+
+impl CommandContext {
+  pub(crate) fn new(cancellation: CancellationToken) -> Self {
+    CommandContext {
+      cancellation,
+      input: None,
+      prompt: false,
+    }
+  }
+
+  pub(crate) fn with_input(mut self, input: Option<Value>) -> Self {
+    self.input = input;
+    self
+  }
+
+  pub(crate) fn with_prompt(mut self, prompt: bool) -> Self {
+    self.prompt = prompt;
+    self
+  }
+
+  /// The context of the running command. Outside of a command (or when run
+  /// through `Shell::run_command`) this is a context that never cancels.
+  pub fn current() -> CommandContext {
+    CONTEXT.try_with(|ctx| ctx.clone()).unwrap_or_default()
+  }
+
+  pub fn cancellation_token(&self) -> CancellationToken {
+    self.cancellation.clone()
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.cancellation.is_cancelled()
+  }
+
+  /// The result of the previous command in a pipeline (`a | b`), if any.
+  pub fn input(&self) -> Option<&Value> {
+    self.input.as_ref()
+  }
+
+  /// True when missing required arguments should be asked for instead of
+  /// failing, see `parse_arguments`.
+  pub fn prompts_for_missing(&self) -> bool {
+    self.prompt
+  }
+
+  /// Runs `fut` with this as the current context.
+  pub(crate) async fn scope<F: Future>(self, fut: F) -> F::Output {
+    CONTEXT.scope(self, fut).await
+  }
+}
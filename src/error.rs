@@ -5,24 +5,79 @@ use crate::__dev::ExceptionWithTrace;
 pub enum Error {
   #[error("arg parse error: {0}")]
   ArgParseError(String),
+  #[error("invalid value for {name}: {reason}")]
+  InvalidArgument { name: String, reason: String },
   #[error("Please provide a subcommand. Available subcommands: {0}")]
   MissingSubcommand(String),
   #[error("No such subcommand. ie. ./[bin] [command] [subcommand]")]
   NoSuchSubcommand,
   #[error("Command not found: {0}")]
   CommandNotFound(String),
+  #[error("Command cancelled")]
+  Cancelled,
+  #[error("Command panicked: {}", .0.message.as_deref().unwrap_or("unknown panic"))]
+  Panic(Box<ExceptionWithTrace>),
+  /// A command in a script run with `source` or `--script` failed.
+  #[error("{path}:{line}: {source}")]
+  Script {
+    path: String,
+    line: usize,
+    source: Box<Error>,
+  },
+  /// Ends the command with a specific exit code in one-shot mode.
+  #[error("{message}")]
+  Exit { code: i32, message: String },
   #[error(transparent)]
   Other(#[from] anyhow::Error),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl Error {
+  pub fn exit(code: i32, message: impl Into<String>) -> Self {
+    Error::Exit {
+      code,
+      message: message.into(),
+    }
+  }
+
+  /// The status one-shot mode exits with: 2 when the command line itself
+  /// is wrong, 130 when cancelled, 101 when it panicked (like Rust's own
+  /// panics) and 1 when the command failed.
+  pub fn exit_code(&self) -> i32 {
+    match self {
+      Error::ArgParseError(_)
+      | Error::InvalidArgument { .. }
+      | Error::MissingSubcommand(_)
+      | Error::NoSuchSubcommand
+      | Error::CommandNotFound(_) => 2,
+      Error::Cancelled => 130,
+      Error::Panic(_) => 101,
+      Error::Exit { code, .. } => *code,
+      Error::Script { source, .. } => source.exit_code(),
+      // Commands returning anyhow::Result can still wrap an `Error::Exit`
+      Error::Other(error) => error.downcast_ref::<Error>().map_or(1, Error::exit_code),
+    }
+  }
+}
+
 pub trait ToTrace {
   fn to_trace(&self) -> ExceptionWithTrace;
 }
 
 impl ToTrace for Error {
   fn to_trace(&self) -> ExceptionWithTrace {
+    // Already captured by the panic hook, with its own filtered range
+    if let Error::Panic(exception) = self {
+      return exception.as_ref().clone();
+    }
+    // The failing command's trace, under a message that says where it was
+    if let Error::Script { source, .. } = self {
+      let mut exception = source.to_trace();
+      exception.message = Some(self.to_string());
+      return exception;
+    }
+
     let message = Some(format!("{}", self));
     let mut backtrace = None;
 
@@ -40,9 +95,14 @@ impl ToTrace for Error {
         sources
       }
       Error::ArgParseError(_) => vec![],
+      Error::InvalidArgument { .. } => vec![],
       Error::MissingSubcommand(_) => vec![],
       Error::NoSuchSubcommand => vec![],
       Error::CommandNotFound(_) => vec![],
+      Error::Cancelled => vec![],
+      Error::Panic(_) => vec![],
+      Error::Script { .. } => vec![],
+      Error::Exit { .. } => vec![],
     };
 
     let mut exception = ExceptionWithTrace::with_sources(message, sources, backtrace);
@@ -136,6 +196,19 @@ mod test {
     );
   }
 
+  #[test]
+  pub fn test_exit_codes() {
+    assert_eq!(Error::CommandNotFound("nope".to_string()).exit_code(), 2);
+    assert_eq!(Error::ArgParseError("bad".to_string()).exit_code(), 2);
+    assert_eq!(Error::Cancelled.exit_code(), 130);
+    assert_eq!(Error::exit(3, "not ready").exit_code(), 3);
+    assert_eq!(Error::Other(anyhow!("oh no")).exit_code(), 1);
+    assert_eq!(
+      Error::Other(anyhow::Error::new(Error::exit(4, "wrapped"))).exit_code(),
+      4
+    );
+  }
+
   // Helper functions to create nested errors
   fn test_error_parent() -> anyhow::Result<()> {
     test_error_child_1().context("parent")?;
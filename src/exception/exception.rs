@@ -1,6 +1,10 @@
 use std::error::Error as StdError;
+use std::fmt::Write;
+use std::ops::CoroutineState;
+use std::pin::pin;
 use std::{backtrace::Backtrace, ops::Coroutine, panic::PanicHookInfo};
 
+use colored::Colorize;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
@@ -12,6 +16,9 @@ static FRAME_REGEX: Lazy<Regex> = Lazy::new(|| {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ExceptionWithTrace {
   pub message: Option<String>,
+  /// Where a panic happened, eg. `src/main.rs:42:5`.
+  #[serde(default)]
+  pub location: Option<String>,
   pub sources: Vec<String>,
   pub frames: Vec<FrameSymbol>,
   pub filtered_range: (Option<String>, Option<String>),
@@ -21,6 +28,7 @@ impl ExceptionWithTrace {
   pub fn new<'a>(message: Option<String>, trace: Option<&Backtrace>) -> Self {
     ExceptionWithTrace {
       message,
+      location: None,
       sources: Vec::new(),
       frames: trace.map_or_else(Vec::new, |t| ExceptionWithTrace::parse_frames(t)),
       filtered_range: (None, None),
@@ -34,6 +42,7 @@ impl ExceptionWithTrace {
   ) -> Self {
     ExceptionWithTrace {
       message,
+      location: None,
       sources,
       frames: trace.map_or_else(Vec::new, |t| ExceptionWithTrace::parse_frames(t)),
       filtered_range: (None, None),
@@ -42,7 +51,6 @@ impl ExceptionWithTrace {
 
   pub fn parse_message(info: &PanicHookInfo<'_>) -> Option<String> {
     if let Some(s) = info.payload().downcast_ref::<&str>() {
-      println!("{s}");
       s.splitn(2, "\n").next().map(|s| s.to_string())
     } else if let Some(s) = info.payload().downcast_ref::<String>() {
       s.splitn(2, "\n").next().map(|s| s.to_string())
@@ -120,6 +128,48 @@ impl ExceptionWithTrace {
       }
     }
   }
+
+  /// Formats the message, its sources and the filtered frames for a
+  /// terminal, eg.
+  ///
+  /// ```text
+  /// error: couldn't deploy
+  ///   caused by: connection refused
+  ///   at simple::deploy (src/main.rs:42)
+  /// ```
+  pub fn render(&self) -> String {
+    let mut out = format!(
+      "{} {}",
+      "error:".red().bold(),
+      self.message.as_deref().unwrap_or("unknown error").bold()
+    );
+    if let Some(location) = &self.location {
+      let _ = write!(out, "\n  {} {}", "panicked at".dimmed(), location);
+    }
+    for source in &self.sources {
+      let _ = write!(out, "\n  {} {}", "caused by:".yellow(), source);
+    }
+
+    let mut frames = pin!(self.filtered_frames());
+    while let CoroutineState::Yielded((_, frame)) = frames.as_mut().resume(()) {
+      if frame.is_core_or_stdlib() || frame.is_dependency() {
+        continue;
+      }
+      let location = match (&frame.file, frame.line) {
+        (Some(file), Some(line)) => format!(" ({file}:{line})"),
+        (Some(file), None) => format!(" ({file})"),
+        _ => String::new(),
+      };
+      let _ = write!(
+        out,
+        "\n  {} {}{}",
+        "at".dimmed(),
+        frame.func,
+        location.dimmed()
+      );
+    }
+    out
+  }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -133,6 +183,16 @@ impl FrameSymbol {
   pub fn is_core_or_stdlib(&self) -> bool {
     self.func.starts_with("core::") || self.func.starts_with("std::")
   }
+
+  /// True for frames in the standard library or a crate from the registry,
+  /// eg. tokio's scheduler or `impl Future` adapters.
+  pub fn is_dependency(&self) -> bool {
+    self.file.as_deref().is_some_and(|file| {
+      file.starts_with("/rustc/")
+        || file.contains("/.cargo/registry/")
+        || file.contains("/.cargo/git/")
+    })
+  }
 }
 
 /// Extract source chain from an anyhow error
@@ -207,6 +267,15 @@ mod tests {
       line: None,
     };
     assert!(!custom_symbol.is_core_or_stdlib());
+    assert!(!custom_symbol.is_dependency());
+
+    // Registry crates are dependencies whatever the function is called
+    let tokio_symbol = FrameSymbol {
+      func: "<tokio::runtime::Runtime>::block_on".to_string(),
+      file: Some("/root/.cargo/registry/src/tokio-1.44.0/src/runtime/runtime.rs".to_string()),
+      line: Some(368),
+    };
+    assert!(tokio_symbol.is_dependency());
   }
 
   #[test]
@@ -270,6 +339,7 @@ mod tests {
     let frames = create_test_frames();
     let exception = ExceptionWithTrace {
       message: Some("test error".to_string()),
+      location: None,
       sources: Vec::new(),
       frames: frames.clone(),
       filtered_range: (
@@ -289,6 +359,7 @@ mod tests {
     // Test case 2: With no start, only end
     let exception = ExceptionWithTrace {
       message: Some("test error".to_string()),
+      location: None,
       sources: Vec::new(),
       frames: frames.clone(),
       filtered_range: (None, Some("end_function".to_string())),
@@ -305,6 +376,7 @@ mod tests {
     // Test case 3: With start, no end
     let exception = ExceptionWithTrace {
       message: Some("test error".to_string()),
+      location: None,
       sources: Vec::new(),
       frames: frames.clone(),
       filtered_range: (Some("start_function".to_string()), None),
@@ -330,6 +402,7 @@ mod tests {
     // Test case 4: With no start and no end
     let exception = ExceptionWithTrace {
       message: Some("test error".to_string()),
+      location: None,
       sources: Vec::new(),
       frames: frames.clone(),
       filtered_range: (None, None),
@@ -341,6 +414,26 @@ mod tests {
     assert_eq!(filtered.len(), 5);
   }
 
+  #[test]
+  fn test_render() {
+    colored::control::set_override(false);
+    let exception = ExceptionWithTrace {
+      message: Some("couldn't deploy".to_string()),
+      location: None,
+      sources: vec!["connection refused".to_string()],
+      frames: create_test_frames(),
+      filtered_range: (
+        Some("start_function".to_string()),
+        Some("end_function".to_string()),
+      ),
+    };
+
+    assert_eq!(
+      exception.render(),
+      "error: couldn't deploy\n  caused by: connection refused\n  at middle_function (/path/to/file3.rs:30)"
+    );
+  }
+
   // Helper function to create test frames
   fn create_test_frames() -> Vec<FrameSymbol> {
     vec![
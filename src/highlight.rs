@@ -0,0 +1,307 @@
+use std::ops::Range;
+use std::sync::{Arc, RwLock};
+
+use nu_ansi_term::{Color, Style};
+use reedline::{Highlighter, StyledText};
+
+use crate::command_tree::{CommandNode, CommandTree};
+use crate::tokenizer::{Class, IntoArgs, Token, classify};
+
+/// Colors the line as it's typed: known commands and namespaces in green,
+/// unknown ones in red, the command's `--flags` in blue (red when it has no
+/// such flag), quoted strings in yellow and variables in magenta.
+///
+/// The line is split by the same tokenizer that runs it, so quotes, escapes
+/// and operators are colored the way they'll be parsed.
+#[derive(Clone)]
+pub struct ShellHighlighter {
+  commands: Arc<RwLock<CommandTree>>,
+}
+
+/// A word of the line, as the byte ranges the tokenizer read it in.
+struct Word<'a> {
+  /// Where `parts` starts in the whole line's classes.
+  index: usize,
+  parts: &'a [(Range<usize>, Class)],
+}
+
+impl Word<'_> {
+  fn range(&self) -> Range<usize> {
+    self.parts[0].0.start..self.parts[self.parts.len() - 1].0.end
+  }
+
+  /// The word as the command will receive it, with quotes and escapes
+  /// removed.
+  fn text(&self, line: &str) -> String {
+    match (&line[self.range()]).try_into_tokens() {
+      Ok(tokens) => match tokens.into_iter().next() {
+        Some(Token::Word(word)) => word,
+        _ => String::new(),
+      },
+      // An unclosed quote at the end of the line
+      Err(_) => line[self.range()].to_string(),
+    }
+  }
+
+  fn has_variable(&self) -> bool {
+    self
+      .parts
+      .iter()
+      .any(|(_, class)| *class == Class::Variable)
+  }
+}
+
+impl ShellHighlighter {
+  pub fn new(commands: Arc<RwLock<CommandTree>>) -> Self {
+    ShellHighlighter { commands }
+  }
+
+  /// Picks the style of each word of one command, by looking its name and
+  /// flags up in the command tree.
+  fn command_styles(&self, line: &str, words: &[Word]) -> Vec<Option<Style>> {
+    let commands = self.commands.read().expect("");
+    let mut styles = vec![None; words.len()];
+    let mut tree: &CommandTree = &commands;
+    let mut node: Option<&CommandNode> = None;
+
+    for (i, word) in words.iter().enumerate() {
+      let text = word.text(line);
+      let Some(node) = node else {
+        // Variables are only known when the command runs
+        if word.has_variable() || text.starts_with('-') {
+          return styles;
+        }
+        match tree.get(&text) {
+          Some(found) => {
+            styles[i] = Some(Color::Green.normal());
+            match &found.children {
+              Some(children) => tree = children,
+              None => node = Some(found),
+            }
+          }
+          None => {
+            styles[i] = Some(Color::Red.normal());
+            return styles;
+          }
+        }
+        continue;
+      };
+      if is_flag(&text) {
+        styles[i] = Some(if knows_flag(node, &text) {
+          Color::Blue.normal()
+        } else {
+          Color::Red.normal()
+        });
+      }
+    }
+    styles
+  }
+}
+
+impl Highlighter for ShellHighlighter {
+  fn highlight(&self, line: &str, _cursor: usize) -> StyledText {
+    let mut styled = StyledText::new();
+    // `!cmd` belongs to the system shell
+    if line.trim_start().starts_with('!') {
+      styled.push((Style::new(), line.to_string()));
+      return styled;
+    }
+
+    let classes = classify(line);
+    let mut styles = vec![None; classes.len()];
+    let mut command_start = 0;
+    // After `>` or `>>` comes a file name rather than a command
+    let mut is_command = true;
+    for i in 0..=classes.len() {
+      let operator = match classes.get(i) {
+        Some((range, Class::Operator)) => Some(range.clone()),
+        Some(_) => continue,
+        None => None,
+      };
+      if is_command {
+        let words = words(&classes[command_start..i], command_start);
+        for (word, style) in words.iter().zip(self.command_styles(line, &words)) {
+          styles[word.index..word.index + word.parts.len()].fill(style);
+        }
+      }
+      let Some(range) = operator else {
+        break;
+      };
+      is_command = !matches!(&line[range], ">" | ">>");
+      command_start = i + 1;
+    }
+
+    for ((range, class), style) in classes.iter().zip(styles) {
+      let style = match class {
+        Class::Quoted => Color::Yellow.normal(),
+        Class::Variable => Color::Magenta.normal(),
+        _ => style.unwrap_or_default(),
+      };
+      match styled.buffer.last_mut() {
+        Some((last, text)) if *last == style => text.push_str(&line[range.clone()]),
+        _ => styled.push((style, line[range.clone()].to_string())),
+      }
+    }
+    styled
+  }
+}
+
+/// Groups the parts of one command into words, at the spaces between them.
+/// `index` is where `classes` starts in the whole line's.
+fn words(classes: &[(Range<usize>, Class)], mut index: usize) -> Vec<Word<'_>> {
+  let mut words = vec![];
+  for parts in classes.split(|(_, class)| *class == Class::Space) {
+    if !parts.is_empty() {
+      words.push(Word { index, parts });
+    }
+    index += parts.len() + 1;
+  }
+  words
+}
+
+/// Whether `word` is spelled like a flag, rather than a value such as `-5`.
+fn is_flag(word: &str) -> bool {
+  let name = word.split('=').next().unwrap_or(word);
+  match name.strip_prefix("--") {
+    Some(long) => !long.is_empty(),
+    None => match name.strip_prefix('-') {
+      Some(short) => !short.is_empty() && short.chars().all(char::is_alphabetic),
+      None => false,
+    },
+  }
+}
+
+/// Whether the command accepts `word`, including bundled short flags like
+/// `-rf`.
+fn knows_flag(node: &CommandNode, word: &str) -> bool {
+  let name = word.split('=').next().unwrap_or(word);
+  let known =
+    |flag: &str| flag == "--help" || flag == "-h" || node.flags.iter().flatten().any(|f| f == flag);
+  if name.starts_with("--") {
+    known(name)
+  } else {
+    name[1..].chars().all(|c| known(&format!("-{c}")))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn highlighter() -> ShellHighlighter {
+    let mut status = CommandTree::new();
+    status.add_command("print", "Prints the status", &[]);
+
+    let mut tree = CommandTree::new();
+    tree.add_command(
+      "deploy",
+      "Deploys the app",
+      &[
+        "-e, --environment, --env: String".to_string(),
+        "-f, --force: Option<bool>".to_string(),
+      ],
+    );
+    tree.add_namespace("status", status);
+    ShellHighlighter::new(Arc::new(RwLock::new(tree)))
+  }
+
+  fn colors(line: &str) -> Vec<(Option<Color>, String)> {
+    highlighter()
+      .highlight(line, line.len())
+      .buffer
+      .into_iter()
+      .map(|(style, text)| (style.foreground, text))
+      .collect()
+  }
+
+  fn span(color: Option<Color>, text: &str) -> (Option<Color>, String) {
+    (color, text.to_string())
+  }
+
+  #[test]
+  fn test_highlight_commands() {
+    assert_eq!(
+      colors("status print"),
+      vec![
+        span(Some(Color::Green), "status"),
+        span(None, " "),
+        span(Some(Color::Green), "print"),
+      ]
+    );
+    assert_eq!(
+      colors("deplyo --env prod"),
+      vec![span(Some(Color::Red), "deplyo"), span(None, " --env prod")]
+    );
+    assert_eq!(
+      colors("status prnt"),
+      vec![
+        span(Some(Color::Green), "status"),
+        span(None, " "),
+        span(Some(Color::Red), "prnt"),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_highlight_flags() {
+    assert_eq!(
+      colors("deploy --env=prod -fe x --nope -5"),
+      vec![
+        span(Some(Color::Green), "deploy"),
+        span(None, " "),
+        span(Some(Color::Blue), "--env=prod"),
+        span(None, " "),
+        span(Some(Color::Blue), "-fe"),
+        span(None, " x "),
+        span(Some(Color::Red), "--nope"),
+        span(None, " -5"),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_highlight_quotes_and_variables() {
+    assert_eq!(
+      colors(r#"deploy --env "$env-1" 'a | b' \$x"#),
+      vec![
+        span(Some(Color::Green), "deploy"),
+        span(None, " "),
+        span(Some(Color::Blue), "--env"),
+        span(None, " "),
+        span(Some(Color::Yellow), "\""),
+        span(Some(Color::Magenta), "$env"),
+        span(Some(Color::Yellow), "-1\""),
+        span(None, " "),
+        span(Some(Color::Yellow), "'a | b'"),
+        span(None, r" \$x"),
+      ]
+    );
+    // Still colored while the quote is open
+    assert_eq!(
+      colors("deploy 'prod"),
+      vec![
+        span(Some(Color::Green), "deploy"),
+        span(None, " "),
+        span(Some(Color::Yellow), "'prod"),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_highlight_chains() {
+    assert_eq!(
+      colors("deploy && nope | status print > out.json"),
+      vec![
+        span(Some(Color::Green), "deploy"),
+        span(None, " && "),
+        span(Some(Color::Red), "nope"),
+        span(None, " | "),
+        span(Some(Color::Green), "status"),
+        span(None, " "),
+        span(Some(Color::Green), "print"),
+        span(None, " > out.json"),
+      ]
+    );
+    assert_eq!(colors("!ls -la"), vec![span(None, "!ls -la")]);
+  }
+}
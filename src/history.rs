@@ -0,0 +1,262 @@
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use anyhow::Context;
+use reedline::{
+  FileBackedHistory, History, HistoryItem, HistoryItemId, HistorySessionId, SearchQuery,
+};
+use tracing::warn;
+
+use crate::command_tree::CommandTree;
+
+const DEFAULT_CAPACITY: usize = 100;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Storage {
+  Disabled,
+  Memory,
+  File(PathBuf),
+  #[cfg(feature = "sqlite")]
+  Sqlite(PathBuf),
+}
+
+/// Where `DefaultLineReader` keeps the commands typed at the prompt, and
+/// how many.
+///
+/// ```ignore
+/// Shell::new(info).history(HistoryConfig::for_app("deployer").capacity(1000))
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HistoryConfig {
+  storage: Storage,
+  capacity: usize,
+}
+
+impl HistoryConfig {
+  /// `history.txt` in the app's data directory, eg.
+  /// `~/.local/share/<app>/history.txt` on Linux.
+  pub fn for_app(app: &str) -> Self {
+    Self::file(app_data_dir(app).join("history.txt"))
+  }
+
+  pub fn file(path: impl Into<PathBuf>) -> Self {
+    HistoryConfig {
+      storage: Storage::File(path.into()),
+      capacity: DEFAULT_CAPACITY,
+    }
+  }
+
+  /// Up-arrow works within a session, but nothing is written to disk.
+  pub fn in_memory() -> Self {
+    HistoryConfig {
+      storage: Storage::Memory,
+      capacity: DEFAULT_CAPACITY,
+    }
+  }
+
+  pub fn disabled() -> Self {
+    HistoryConfig {
+      storage: Storage::Disabled,
+      capacity: 0,
+    }
+  }
+
+  /// A SQLite database that also records when each command ran, from which
+  /// directory, how long it took and its exit status.
+  #[cfg(feature = "sqlite")]
+  pub fn sqlite(path: impl Into<PathBuf>) -> Self {
+    HistoryConfig {
+      storage: Storage::Sqlite(path.into()),
+      capacity: DEFAULT_CAPACITY,
+    }
+  }
+
+  /// `history.sqlite3` in the app's data directory.
+  #[cfg(feature = "sqlite")]
+  pub fn sqlite_for_app(app: &str) -> Self {
+    Self::sqlite(app_data_dir(app).join("history.sqlite3"))
+  }
+
+  /// How many commands a file or in-memory history keeps. Has no effect on
+  /// SQLite history, which keeps everything.
+  pub fn capacity(mut self, capacity: usize) -> Self {
+    self.capacity = capacity;
+    self
+  }
+
+  pub fn path(&self) -> Option<&PathBuf> {
+    match &self.storage {
+      Storage::File(path) => Some(path),
+      #[cfg(feature = "sqlite")]
+      Storage::Sqlite(path) => Some(path),
+      Storage::Disabled | Storage::Memory => None,
+    }
+  }
+
+  /// True when the history stores per-command context (cwd, exit status).
+  pub(crate) fn records_context(&self) -> bool {
+    match &self.storage {
+      #[cfg(feature = "sqlite")]
+      Storage::Sqlite(_) => true,
+      _ => false,
+    }
+  }
+
+  /// Opens the history. If the file can't be created it falls back to an
+  /// in-memory history rather than refusing to start the shell. `session`
+  /// tags what the sqlite history saves and must be the id reedline uses.
+  pub(crate) fn build(&self, session: Option<HistorySessionId>) -> Box<dyn History> {
+    self.try_build(session).unwrap_or_else(|e| {
+      warn!("History disabled: {e:#}");
+      Box::new(FileBackedHistory::new(self.capacity).expect("in-memory history"))
+    })
+  }
+
+  #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+  fn try_build(&self, session: Option<HistorySessionId>) -> anyhow::Result<Box<dyn History>> {
+    if let Some(dir) = self.path().and_then(|path| path.parent()) {
+      std::fs::create_dir_all(dir).with_context(|| format!("couldn't create {}", dir.display()))?;
+    }
+    Ok(match &self.storage {
+      Storage::Disabled => Box::new(FileBackedHistory::new(0)?),
+      Storage::Memory => Box::new(FileBackedHistory::new(self.capacity)?),
+      Storage::File(path) => Box::new(FileBackedHistory::with_file(self.capacity, path.clone())?),
+      #[cfg(feature = "sqlite")]
+      Storage::Sqlite(path) => Box::new(reedline::SqliteBackedHistory::with_file(
+        path.clone(),
+        session,
+        Some(chrono::Utc::now()),
+      )?),
+    })
+  }
+}
+
+impl Default for HistoryConfig {
+  /// A history file named after the running executable.
+  fn default() -> Self {
+    let app = std::env::current_exe()
+      .ok()
+      .and_then(|exe| exe.file_stem().map(|s| s.to_string_lossy().into_owned()))
+      .unwrap_or_else(|| "mysh".to_string());
+    Self::for_app(&app)
+  }
+}
+
+/// Masks secret arguments before a line is stored, see `CommandTree::redact`.
+pub(crate) struct RedactedHistory {
+  inner: Box<dyn History>,
+  commands: Arc<RwLock<CommandTree>>,
+}
+
+impl RedactedHistory {
+  pub(crate) fn new(inner: Box<dyn History>, commands: Arc<RwLock<CommandTree>>) -> Self {
+    RedactedHistory { inner, commands }
+  }
+}
+
+impl History for RedactedHistory {
+  fn save(&mut self, mut h: HistoryItem) -> reedline::Result<HistoryItem> {
+    h.command_line = self.commands.read().expect("").redact(&h.command_line);
+    self.inner.save(h)
+  }
+
+  fn load(&self, id: HistoryItemId) -> reedline::Result<HistoryItem> {
+    self.inner.load(id)
+  }
+
+  fn count(&self, query: SearchQuery) -> reedline::Result<i64> {
+    self.inner.count(query)
+  }
+
+  fn search(&self, query: SearchQuery) -> reedline::Result<Vec<HistoryItem>> {
+    self.inner.search(query)
+  }
+
+  fn update(
+    &mut self,
+    id: HistoryItemId,
+    updater: &dyn Fn(HistoryItem) -> HistoryItem,
+  ) -> reedline::Result<()> {
+    self.inner.update(id, updater)
+  }
+
+  fn clear(&mut self) -> reedline::Result<()> {
+    self.inner.clear()
+  }
+
+  fn delete(&mut self, h: HistoryItemId) -> reedline::Result<()> {
+    self.inner.delete(h)
+  }
+
+  fn sync(&mut self) -> std::io::Result<()> {
+    self.inner.sync()
+  }
+
+  fn session(&self) -> Option<HistorySessionId> {
+    self.inner.session()
+  }
+}
+
+fn app_data_dir(app: &str) -> PathBuf {
+  dirs::data_dir()
+    .unwrap_or_else(|| PathBuf::from("."))
+    .join(app)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_history_paths() {
+    let config = HistoryConfig::for_app("deployer");
+    let path = config.path().unwrap();
+    assert!(path.ends_with("deployer/history.txt"));
+    assert_eq!(config.capacity, DEFAULT_CAPACITY);
+
+    assert_eq!(HistoryConfig::in_memory().capacity(5).path(), None);
+    assert_eq!(HistoryConfig::disabled().path(), None);
+  }
+
+  #[test]
+  fn test_build_history() {
+    let dir = std::env::temp_dir().join(format!("mysh-history-{}", std::process::id()));
+    let path = dir.join("nested/history.txt");
+    let mut history = HistoryConfig::file(&path).capacity(2).build(None);
+    history
+      .save(reedline::HistoryItem::from_command_line("status print"))
+      .unwrap();
+    history.sync().unwrap();
+    assert!(path.exists());
+    std::fs::remove_dir_all(dir).unwrap();
+
+    // Nothing sticks when history is disabled
+    let mut history = HistoryConfig::disabled().build(None);
+    history
+      .save(reedline::HistoryItem::from_command_line("status print"))
+      .unwrap();
+    assert_eq!(history.count_all().unwrap(), 0);
+  }
+
+  #[test]
+  fn test_redacted_history() {
+    let mut tree = CommandTree::new();
+    tree.add_command(
+      "login",
+      "Logs in",
+      &["-t, --token: String (secret)".to_string()],
+    );
+    tree.set_secrets("login", vec!["-t".to_string(), "--token".to_string()]);
+    let commands = Arc::new(RwLock::new(tree));
+    let mut history = RedactedHistory::new(HistoryConfig::in_memory().build(None), commands);
+
+    let saved = history
+      .save(HistoryItem::from_command_line("login --token abc123"))
+      .unwrap();
+    assert_eq!(saved.command_line, "login --token ***");
+    let saved = history
+      .save(HistoryItem::from_command_line("status print"))
+      .unwrap();
+    assert_eq!(saved.command_line, "status print");
+  }
+}
@@ -0,0 +1,139 @@
+use serde_json::Value;
+use std::future::Future;
+use std::sync::{Mutex, Once};
+use std::time::Duration;
+use tracing::warn;
+
+use crate::context::{CancellationToken, CommandContext};
+use crate::error::Error;
+
+/// How long a cancelled command gets to notice its token and clean up
+/// before its future is dropped.
+const GRACE_PERIOD: Duration = Duration::from_millis(500);
+
+static CURRENT: Mutex<Option<CancellationToken>> = Mutex::new(None);
+static INSTALL: Once = Once::new();
+
+/// Routes Ctrl-C to the running command instead of killing the process.
+/// While reedline is reading a line the terminal is in raw mode, so Ctrl-C
+/// reaches it as a key press rather than a signal. A signal with nothing
+/// running (eg. while blocked on piped stdin) exits like it would by default.
+pub(crate) fn install_handler() {
+  INSTALL.call_once(|| {
+    if let Err(e) = ctrlc::set_handler(on_ctrl_c) {
+      warn!("Couldn't install the Ctrl-C handler: {e}");
+    }
+  });
+}
+
+/// What Ctrl-C does.
+#[derive(Debug, PartialEq, Eq)]
+enum Interrupt {
+  Cancelled,
+  /// Nothing is running, or the command is stuck in blocking code and never
+  /// looked at the token.
+  Exit,
+}
+
+fn on_ctrl_c() {
+  let current = CURRENT.lock().expect("").clone();
+  if interrupt(current.as_ref()) == Interrupt::Exit {
+    std::process::exit(130);
+  }
+}
+
+/// Cancels `current`, the running command's token, unless it's time to exit.
+fn interrupt(current: Option<&CancellationToken>) -> Interrupt {
+  match current {
+    Some(token) if !token.is_cancelled() => {
+      token.cancel();
+      Interrupt::Cancelled
+    }
+    _ => Interrupt::Exit,
+  }
+}
+
+/// Marks a token as the one Ctrl-C cancels, until dropped.
+struct Active;
+
+impl Active {
+  fn set(token: CancellationToken) -> Self {
+    *CURRENT.lock().expect("") = Some(token);
+    Active
+  }
+}
+
+impl Drop for Active {
+  fn drop(&mut self) {
+    *CURRENT.lock().expect("") = None;
+  }
+}
+
+/// Runs a command so that Ctrl-C cancels it, returning `Error::Cancelled`
+/// in that case.
+pub(crate) async fn cancellable<F>(fut: F) -> crate::Result<Value>
+where
+  F: Future<Output = crate::Result<Value>>,
+{
+  let token = CancellationToken::new();
+  let _active = Active::set(token.clone());
+
+  let fut = CommandContext::new(token.clone()).scope(fut);
+  tokio::pin!(fut);
+  tokio::select! {
+    // A command that returns because it saw the token still counts as cancelled
+    biased;
+    _ = token.cancelled() => {}
+    result = &mut fut => return result,
+  }
+
+  let _ = tokio::time::timeout(GRACE_PERIOD, fut).await;
+  Err(Error::Cancelled)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::Arc;
+  use std::sync::atomic::{AtomicBool, Ordering};
+
+  #[tokio::test]
+  async fn test_interrupt_cancels_running_command() {
+    let cleaned_up = Arc::new(AtomicBool::new(false));
+    let command = {
+      let cleaned_up = cleaned_up.clone();
+      async move {
+        let ctx = CommandContext::current();
+        ctx.cancellation_token().cancelled().await;
+        cleaned_up.store(true, Ordering::SeqCst);
+        Ok(Value::Null)
+      }
+    };
+
+    tokio::spawn(async {
+      tokio::time::sleep(Duration::from_millis(20)).await;
+      let current = CURRENT.lock().unwrap().clone();
+      assert_eq!(interrupt(current.as_ref()), Interrupt::Cancelled);
+    });
+
+    assert!(matches!(cancellable(command).await, Err(Error::Cancelled)));
+    assert!(cleaned_up.load(Ordering::SeqCst));
+    assert!(CURRENT.lock().unwrap().is_none());
+
+    // Outside of `cancellable` there's nothing to cancel
+    assert!(!CommandContext::current().is_cancelled());
+    let result = cancellable(async { Ok(Value::Bool(true)) }).await;
+    assert_eq!(result.unwrap(), Value::Bool(true));
+  }
+
+  #[test]
+  fn test_interrupt_exits_when_nothing_is_cancelled() {
+    assert_eq!(interrupt(None), Interrupt::Exit);
+
+    let token = CancellationToken::new();
+    assert_eq!(interrupt(Some(&token)), Interrupt::Cancelled);
+    assert!(token.is_cancelled());
+    // Pressed again while the command ignores the first one
+    assert_eq!(interrupt(Some(&token)), Interrupt::Exit);
+  }
+}
@@ -1,23 +1,43 @@
 #![feature(coroutines)]
 #![feature(coroutine_trait)]
+#![cfg_attr(test, feature(iter_from_coroutine))]
 
+mod arg_schema;
 mod command_arg;
 mod command_list;
 mod command_metadata;
+mod command_tree;
+mod completion;
+mod context;
 mod error;
 mod exception;
+mod highlight;
+mod history;
+mod interrupt;
+mod output;
+mod panic_hook;
+mod process;
+mod redirect;
+mod results;
 mod run_loop;
+mod script;
 mod shell;
 mod tokenizer;
+mod validation;
 
 pub use mysh_derive::*;
 
-pub use command_arg::{CommandArg, parse_arguments};
+pub use command_arg::{ArgSpec, CommandArg, parse_arguments, parse_arguments_prompting};
 pub use command_metadata::CommandMetadata;
+pub use context::{CancellationToken, CommandContext};
 pub use error::{Error, Result};
 pub use futures;
+pub use history::HistoryConfig;
+pub use output::OutputFormat;
+pub use process::{Process, ProcessOutput};
 pub use reedline::ExternalPrinter;
-pub use shell::{DefaultLineReader, PromptText};
+pub use run_loop::ErrorOutput;
+pub use shell::{DefaultLineReader, PromptText, StdinLineReader};
 pub use shell::{Scripts, Shell};
 
 pub mod json {
@@ -25,6 +45,8 @@ pub mod json {
   pub use serde_json::to_value;
 }
 
+/// Runs a program with the terminal attached and returns its `ExitStatus`.
+/// See `run!` to capture what it prints.
 #[macro_export]
 macro_rules! shell {
   ($cmd:expr, [$($arg:expr),* $(,)?]) => {{
@@ -36,6 +58,25 @@ macro_rules! shell {
   }};
 }
 
+/// Runs a program and captures its stdout and stderr. Awaiting it gives a
+/// `mysh::Result<ProcessOutput>` that is an error when the program exits
+/// with a non-zero status, or when the command is cancelled (which kills the
+/// program). `env` and `cwd` are optional.
+///
+/// ```ignore
+/// let out = mysh::run!("make", ["build"], env = [("CI", "1")], cwd = "/src").await?;
+/// println!("{}", out.stdout);
+/// ```
+#[macro_export]
+macro_rules! run {
+  ($cmd:expr $(, [$($arg:expr),* $(,)?])? $(, $option:ident = $value:expr)* $(,)?) => {
+    $crate::Process::new($cmd)
+      $(.args([$($arg),*]))?
+      $(.$option($value))*
+      .output()
+  };
+}
+
 pub mod __dev {
   pub use crate::error::ToTrace;
   pub use crate::exception::{
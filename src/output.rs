@@ -0,0 +1,224 @@
+use serde_json::Value;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// How the value a command returns is printed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+  /// Compact JSON on one line, for piping into `jq` and friends.
+  Json,
+  /// Indented JSON.
+  #[default]
+  Pretty,
+  Yaml,
+  /// Arrays of objects as aligned columns, objects as key/value rows.
+  Table,
+  /// Strings without quotes, one array item or object entry per line.
+  Plain,
+}
+
+impl OutputFormat {
+  pub const ALL: [OutputFormat; 5] = [
+    OutputFormat::Json,
+    OutputFormat::Pretty,
+    OutputFormat::Yaml,
+    OutputFormat::Table,
+    OutputFormat::Plain,
+  ];
+
+  /// Renders `value`, or returns `None` when there's nothing to show (the
+  /// command returned `()`).
+  pub fn render(&self, value: &Value) -> Option<String> {
+    if value.is_null() {
+      return None;
+    }
+    let rendered = match self {
+      OutputFormat::Json => value.to_string(),
+      OutputFormat::Pretty => serde_json::to_string_pretty(value).expect("json value serializes"),
+      OutputFormat::Yaml => serde_yaml::to_string(value)
+        .expect("json value serializes")
+        .trim_end()
+        .to_string(),
+      OutputFormat::Table => table(value).unwrap_or_else(|| plain(value)),
+      OutputFormat::Plain => plain(value),
+    };
+    Some(rendered).filter(|r| !r.is_empty())
+  }
+}
+
+impl Display for OutputFormat {
+  fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+    f.write_str(match self {
+      OutputFormat::Json => "json",
+      OutputFormat::Pretty => "pretty",
+      OutputFormat::Yaml => "yaml",
+      OutputFormat::Table => "table",
+      OutputFormat::Plain => "plain",
+    })
+  }
+}
+
+impl FromStr for OutputFormat {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    OutputFormat::ALL
+      .into_iter()
+      .find(|format| format.to_string() == s)
+      .ok_or_else(|| {
+        let formats: Vec<String> = OutputFormat::ALL.iter().map(|f| f.to_string()).collect();
+        format!("expected one of {}, got {s:?}", formats.join(", "))
+      })
+  }
+}
+
+/// A scalar as text, anything nested as compact JSON.
+fn cell(value: &Value) -> String {
+  match value {
+    Value::Null => String::new(),
+    Value::String(s) => s.clone(),
+    value => value.to_string(),
+  }
+}
+
+fn plain(value: &Value) -> String {
+  match value {
+    Value::Array(items) => items.iter().map(cell).collect::<Vec<_>>().join("\n"),
+    Value::Object(entries) => entries
+      .iter()
+      .map(|(key, value)| format!("{key}: {}", cell(value)))
+      .collect::<Vec<_>>()
+      .join("\n"),
+    value => cell(value),
+  }
+}
+
+/// Returns `None` for values that don't have a table shape.
+fn table(value: &Value) -> Option<String> {
+  match value {
+    Value::Array(items) => {
+      let rows: Vec<&serde_json::Map<String, Value>> =
+        items.iter().map(Value::as_object).collect::<Option<_>>()?;
+      // Columns in the order keys first appear, so sparse rows still line up
+      let mut columns: Vec<&String> = vec![];
+      for row in &rows {
+        for key in row.keys() {
+          if !columns.contains(&key) {
+            columns.push(key);
+          }
+        }
+      }
+      let header = columns.iter().map(|c| c.to_string()).collect();
+      let cells = rows
+        .iter()
+        .map(|row| {
+          columns
+            .iter()
+            .map(|c| row.get(*c).map(cell).unwrap_or_default())
+            .collect()
+        })
+        .collect();
+      Some(align(header, cells))
+    }
+    Value::Object(entries) => {
+      let cells = entries
+        .iter()
+        .map(|(key, value)| vec![key.clone(), cell(value)])
+        .collect();
+      Some(align(vec!["key".to_string(), "value".to_string()], cells))
+    }
+    _ => None,
+  }
+}
+
+fn align(header: Vec<String>, rows: Vec<Vec<String>>) -> String {
+  let width = |s: &String| s.chars().count();
+  let widths: Vec<usize> = (0..header.len())
+    .map(|i| {
+      std::iter::once(&header)
+        .chain(&rows)
+        .map(|row| width(&row[i]))
+        .max()
+        .unwrap_or(0)
+    })
+    .collect();
+  let underline = widths.iter().map(|w| "-".repeat(*w)).collect();
+
+  std::iter::once(&header)
+    .chain(std::iter::once(&underline))
+    .chain(&rows)
+    .map(|row| {
+      row
+        .iter()
+        .zip(&widths)
+        .map(|(cell, w)| format!("{cell}{}", " ".repeat(w - width(cell))))
+        .collect::<Vec<_>>()
+        .join("  ")
+        .trim_end()
+        .to_string()
+    })
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[test]
+  fn test_table_of_objects() {
+    // Keys are sorted within a row, and a key a later row adds goes last
+    let users = json!([
+      {"name": "ana", "team": "dev"},
+      {"name": "bartholomew", "active": false, "team": "ops"},
+    ]);
+    assert_eq!(
+      OutputFormat::Table.render(&users).unwrap(),
+      "name         team  active\n\
+       -----------  ----  ------\n\
+       ana          dev\n\
+       bartholomew  ops   false"
+    );
+  }
+
+  #[test]
+  fn test_table_of_object_and_fallback() {
+    let user = json!({"name": "ana", "tags": ["a", "b"]});
+    assert_eq!(
+      OutputFormat::Table.render(&user).unwrap(),
+      "key   value\n\
+       ----  ---------\n\
+       name  ana\n\
+       tags  [\"a\",\"b\"]"
+    );
+    // Not table shaped, so it's printed plainly
+    assert_eq!(
+      OutputFormat::Table.render(&json!(["a", 1])).unwrap(),
+      "a\n1"
+    );
+  }
+
+  #[test]
+  fn test_render_formats() {
+    let value = json!({"name": "ana"});
+    assert_eq!(
+      OutputFormat::Json.render(&value).unwrap(),
+      r#"{"name":"ana"}"#
+    );
+    assert_eq!(
+      OutputFormat::Pretty.render(&value).unwrap(),
+      "{\n  \"name\": \"ana\"\n}"
+    );
+    assert_eq!(OutputFormat::Yaml.render(&value).unwrap(), "name: ana");
+    assert_eq!(OutputFormat::Plain.render(&value).unwrap(), "name: ana");
+    assert_eq!(OutputFormat::Plain.render(&json!("hi")).unwrap(), "hi");
+    assert_eq!(OutputFormat::Pretty.render(&Value::Null), None);
+  }
+
+  #[test]
+  fn test_parse_format() {
+    assert_eq!("table".parse(), Ok(OutputFormat::Table));
+    assert!("xml".parse::<OutputFormat>().is_err());
+  }
+}
@@ -0,0 +1,103 @@
+use futures::FutureExt;
+use serde_json::Value;
+use std::backtrace::Backtrace;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe, PanicHookInfo};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::__dev::ExceptionWithTrace;
+use crate::error::Error;
+
+static CAPTURED: Mutex<Option<ExceptionWithTrace>> = Mutex::new(None);
+
+type Hook = Box<dyn Fn(&PanicHookInfo<'_>) + Sync + Send + 'static>;
+
+/// Runs a command with a panic hook that records the panic's message,
+/// location and backtrace, and returns it as `Error::Panic` instead of
+/// unwinding further.
+///
+/// Panics on other threads (eg. spawned tasks) still go to the previous hook.
+pub(crate) async fn catch_panics<F>(fut: F) -> crate::Result<Value>
+where
+  F: Future<Output = crate::Result<Value>>,
+{
+  let previous: Arc<Hook> = Arc::new(panic::take_hook());
+  let command_thread = thread::current().id();
+  panic::set_hook({
+    let previous = previous.clone();
+    Box::new(move |info| {
+      if thread::current().id() == command_thread {
+        *CAPTURED.lock().expect("") = Some(capture(info));
+      } else {
+        previous(info);
+      }
+    })
+  });
+
+  let result = AssertUnwindSafe(fut).catch_unwind().await;
+
+  // Dropping our hook releases its handle on the previous one
+  drop(panic::take_hook());
+  match Arc::try_unwrap(previous) {
+    Ok(previous) => panic::set_hook(previous),
+    Err(previous) => panic::set_hook(Box::new(move |info| previous(info))),
+  }
+
+  match result {
+    Ok(result) => result,
+    Err(_) => {
+      let exception = CAPTURED
+        .lock()
+        .expect("")
+        .take()
+        .unwrap_or_else(|| ExceptionWithTrace::new(None, None));
+      Err(Error::Panic(Box::new(exception)))
+    }
+  }
+}
+
+fn capture(info: &PanicHookInfo<'_>) -> ExceptionWithTrace {
+  let backtrace = Backtrace::force_capture();
+  let mut exception =
+    ExceptionWithTrace::new(ExceptionWithTrace::parse_message(info), Some(&backtrace));
+  exception.location = info.location().map(|l| l.to_string());
+  // Skip the hook and the panic machinery, and stop at the run loop
+  exception.filtered_range.0 = Some("core::panicking::panic_fmt".to_string());
+  exception.filtered_range.1 = Some("mysh::run_loop::run_once_or_loop".to_string());
+  exception
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::error::ToTrace;
+
+  #[tokio::test]
+  async fn test_catch_panics() {
+    let result = catch_panics(async {
+      if true {
+        panic!("boom\nmore detail");
+      }
+      Ok(Value::Null)
+    })
+    .await;
+
+    let Err(error @ Error::Panic(_)) = result else {
+      panic!("expected a panic error");
+    };
+    let exception = error.to_trace();
+    assert_eq!(exception.message.as_deref(), Some("boom"));
+    assert!(
+      exception
+        .location
+        .as_deref()
+        .is_some_and(|l| l.starts_with("src/panic_hook.rs:"))
+    );
+    assert!(!exception.frames.is_empty());
+    assert_eq!(error.exit_code(), 101);
+
+    let result = catch_panics(async { Ok(Value::Bool(true)) }).await;
+    assert_eq!(result.unwrap(), Value::Bool(true));
+  }
+}
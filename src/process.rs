@@ -0,0 +1,191 @@
+use std::ffi::OsStr;
+use std::future::Future;
+use std::path::Path;
+use std::process::ExitStatus;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::process::Command;
+
+use crate::context::CommandContext;
+use crate::error::Error;
+
+/// What a finished process printed. Commands can return it as is.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessOutput {
+  pub status: i32,
+  pub stdout: String,
+  pub stderr: String,
+}
+
+/// Runs a program and captures its output, see `run!`.
+///
+/// ```ignore
+/// let out = Process::new("git").args(["rev-parse", "HEAD"]).cwd(repo).output().await?;
+/// ```
+#[derive(Debug)]
+pub struct Process {
+  command: Command,
+}
+
+impl Process {
+  pub fn new(program: impl AsRef<OsStr>) -> Self {
+    Process {
+      command: Command::new(program),
+    }
+  }
+
+  pub fn args<I, S>(mut self, args: I) -> Self
+  where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+  {
+    self.command.args(args);
+    self
+  }
+
+  /// Sets environment variables on top of the ones this process has.
+  pub fn env<I, K, V>(mut self, vars: I) -> Self
+  where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<OsStr>,
+    V: AsRef<OsStr>,
+  {
+    self.command.envs(vars);
+    self
+  }
+
+  pub fn cwd(mut self, dir: impl AsRef<Path>) -> Self {
+    self.command.current_dir(dir);
+    self
+  }
+
+  /// Waits for the program to finish. A non-zero exit status is an
+  /// `Error::Exit` with the same code and the program's stderr as the
+  /// message. The program is killed if the command is cancelled.
+  pub async fn output(mut self) -> crate::Result<ProcessOutput> {
+    let program = self
+      .command
+      .as_std()
+      .get_program()
+      .to_string_lossy()
+      .into_owned();
+    let output = until_cancelled(self.command.kill_on_drop(true).output())
+      .await?
+      .with_context(|| format!("couldn't run {program}"))?;
+    let result = ProcessOutput {
+      status: exit_code(output.status),
+      stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+      stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    };
+    if !output.status.success() {
+      let mut message = format!("{program} exited with {}", result.status);
+      let stderr = result.stderr.trim();
+      if !stderr.is_empty() {
+        message = format!("{message}: {stderr}");
+      }
+      return Err(Error::exit(result.status, message));
+    }
+    Ok(result)
+  }
+}
+
+/// The status a shell would report, `128 + n` for a process killed by
+/// signal `n`.
+fn exit_code(status: ExitStatus) -> i32 {
+  #[cfg(unix)]
+  {
+    use std::os::unix::process::ExitStatusExt;
+    if let Some(signal) = status.signal() {
+      return 128 + signal;
+    }
+  }
+  status.code().unwrap_or(1)
+}
+
+/// Runs `line` with `$SHELL -c` (or `/bin/sh`), for `!cmd` at the prompt.
+/// The program talks to the terminal directly.
+pub(crate) async fn run_system_shell(line: &str) -> crate::Result<Value> {
+  let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
+  let mut command = Command::new(&shell);
+  command.arg("-c").arg(line).kill_on_drop(true);
+  let status = until_cancelled(command.status())
+    .await?
+    .with_context(|| format!("couldn't run {shell}"))?;
+  if !status.success() {
+    let code = exit_code(status);
+    return Err(Error::exit(code, format!("{shell} exited with {code}")));
+  }
+  Ok(Value::Null)
+}
+
+/// Waits for a child process, giving up (which kills it) when the running
+/// command is cancelled.
+async fn until_cancelled<T>(wait: impl Future<Output = T>) -> crate::Result<T> {
+  let token = CommandContext::current().cancellation_token();
+  tokio::select! {
+    result = wait => Ok(result),
+    _ = token.cancelled() => Err(Error::Cancelled),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::context::CancellationToken;
+  use std::time::{Duration, Instant};
+
+  #[tokio::test]
+  async fn test_run_captures_output() {
+    let out = crate::run!(
+      "sh",
+      ["-c", "echo $GREETING from $(pwd); echo oops >&2"],
+      env = [("GREETING", "hi")],
+      cwd = "/",
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+      out,
+      ProcessOutput {
+        status: 0,
+        stdout: "hi from /\n".to_string(),
+        stderr: "oops\n".to_string(),
+      }
+    );
+  }
+
+  #[tokio::test]
+  async fn test_run_fails_on_non_zero_status() {
+    let err = crate::run!("sh", ["-c", "echo no such host >&2; exit 3"])
+      .await
+      .unwrap_err();
+    assert_eq!(err.exit_code(), 3);
+    assert_eq!(err.to_string(), "sh exited with 3: no such host");
+
+    let err = crate::run!("sh", ["-c", "exit 4"]).await.unwrap_err();
+    assert_eq!(err.to_string(), "sh exited with 4");
+
+    assert!(crate::run!("/no/such/program").await.is_err());
+  }
+
+  #[tokio::test]
+  async fn test_cancelling_kills_the_program() {
+    let token = CancellationToken::new();
+    let started = Instant::now();
+    let run = CommandContext::new(token.clone()).scope(crate::run!("sleep", ["5"]));
+    tokio::spawn(async move {
+      tokio::time::sleep(Duration::from_millis(20)).await;
+      token.cancel();
+    });
+    assert!(matches!(run.await, Err(Error::Cancelled)));
+    assert!(started.elapsed() < Duration::from_secs(2));
+  }
+
+  #[tokio::test]
+  async fn test_system_shell_status() {
+    assert_eq!(run_system_shell("true").await.unwrap(), Value::Null);
+    assert_eq!(run_system_shell("exit 5").await.unwrap_err().exit_code(), 5);
+  }
+}
@@ -0,0 +1,119 @@
+use std::io::Write;
+
+use anyhow::Context;
+use serde_json::Value;
+
+use crate::error::Error;
+use crate::tokenizer::{Operator, Token};
+
+/// Where `cmd > file` or `cmd >> file` sends the result.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Redirect {
+  path: String,
+  append: bool,
+}
+
+impl Redirect {
+  /// Takes a trailing `> file` or `>> file` off `tokens`.
+  pub fn take(tokens: &mut Vec<Token>) -> crate::Result<Option<Redirect>> {
+    let position = tokens
+      .iter()
+      .position(|token| matches!(token, Token::Operator(Operator::Write | Operator::Append)));
+    let Some(position) = position else {
+      return Ok(None);
+    };
+    let append = tokens[position] == Token::Operator(Operator::Append);
+    let operator = if append { ">>" } else { ">" };
+    let path = match &tokens[position + 1..] {
+      [Token::Word(path)] => path.clone(),
+      [] => {
+        return Err(Error::ArgParseError(format!(
+          "missing file after {operator}"
+        )));
+      }
+      _ => {
+        return Err(Error::ArgParseError(format!(
+          "{operator} takes one file and must come last"
+        )));
+      }
+    };
+    tokens.truncate(position);
+    Ok(Some(Redirect { path, append }))
+  }
+
+  /// `>` replaces the file with indented JSON, `>>` adds a line of JSON to
+  /// it (NDJSON). A command that returned nothing writes nothing.
+  pub fn write(&self, value: &Value) -> crate::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+      .create(true)
+      .write(true)
+      .append(self.append)
+      .truncate(!self.append)
+      .open(&self.path)
+      .with_context(|| format!("couldn't open {}", self.path))?;
+    if value.is_null() {
+      return Ok(());
+    }
+    let json = if self.append {
+      value.to_string()
+    } else {
+      serde_json::to_string_pretty(value).expect("json value serializes")
+    };
+    writeln!(file, "{json}").with_context(|| format!("couldn't write {}", self.path))?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::tokenizer::IntoArgs;
+  use serde_json::json;
+
+  #[test]
+  fn test_take_redirect() {
+    let mut tokens = "list | count > out.json".try_into_tokens().unwrap();
+    let redirect = Redirect::take(&mut tokens).unwrap().unwrap();
+    assert_eq!(redirect.path, "out.json");
+    assert!(!redirect.append);
+    assert_eq!(tokens, "list | count".try_into_tokens().unwrap());
+
+    let mut tokens = "list".try_into_tokens().unwrap();
+    assert_eq!(Redirect::take(&mut tokens).unwrap(), None);
+
+    for line in ["list >", "list > a b", "list > a | count"] {
+      let mut tokens = line.try_into_tokens().unwrap();
+      assert!(Redirect::take(&mut tokens).is_err(), "{line}");
+    }
+  }
+
+  #[test]
+  fn test_write_and_append() {
+    let dir = std::env::temp_dir().join(format!("mysh-redirect-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+    let write = Redirect {
+      path: path("out.json"),
+      append: false,
+    };
+    write.write(&json!({"a": 1})).unwrap();
+    write.write(&json!({"b": 2})).unwrap();
+    assert_eq!(
+      std::fs::read_to_string(path("out.json")).unwrap(),
+      "{\n  \"b\": 2\n}\n"
+    );
+
+    let append = Redirect {
+      path: path("log.ndjson"),
+      append: true,
+    };
+    append.write(&json!({"a": 1})).unwrap();
+    append.write(&json!([1, 2])).unwrap();
+    assert_eq!(
+      std::fs::read_to_string(path("log.ndjson")).unwrap(),
+      "{\"a\":1}\n[1,2]\n"
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -0,0 +1,99 @@
+use std::collections::VecDeque;
+
+use serde_json::Value;
+
+/// How many results `$1`, `$2`, ... can reach back.
+const CAPACITY: usize = 10;
+
+/// The most recent command results, newest first. `$_` and `$1` are the
+/// last one, `$2` the one before it, and a path picks out a field, eg.
+/// `$_.id` or `$3.items[0].name`.
+#[derive(Debug, Default)]
+pub(crate) struct Results {
+  ring: VecDeque<Value>,
+}
+
+impl Results {
+  /// Keeps `value`, unless it's `null` (the command returned `()`), so a
+  /// command like `set` doesn't push the result you wanted out of `$_`.
+  pub fn push(&mut self, value: &Value) {
+    if value.is_null() {
+      return;
+    }
+    self.ring.push_front(value.clone());
+    self.ring.truncate(CAPACITY);
+  }
+
+  /// Resolves a reference such as `_`, `2` or `_.items[0].id`. Returns
+  /// `None` for names that aren't result references.
+  pub fn get(&self, reference: &str) -> Option<Option<&Value>> {
+    let split = reference.find(['.', '[']).unwrap_or(reference.len());
+    let (head, path) = reference.split_at(split);
+    let index = match head {
+      "_" => 0,
+      head if is_index(head) => head.parse::<usize>().ok()?.checked_sub(1)?,
+      _ => return None,
+    };
+    Some(self.ring.get(index).and_then(|value| select(value, path)))
+  }
+}
+
+fn is_index(name: &str) -> bool {
+  !name.is_empty() && name.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Follows a path like `.items[0].name` into `value`.
+fn select<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+  let mut value = value;
+  let mut rest = path;
+  while !rest.is_empty() {
+    if let Some(field) = rest.strip_prefix('.') {
+      let end = field.find(['.', '[']).unwrap_or(field.len());
+      let (key, tail) = field.split_at(end);
+      value = match value {
+        Value::Array(items) => items.get(key.parse::<usize>().ok()?)?,
+        value => value.get(key)?,
+      };
+      rest = tail;
+    } else {
+      let (index, tail) = rest.strip_prefix('[')?.split_once(']')?;
+      value = value.get(index.parse::<usize>().ok()?)?;
+      rest = tail;
+    }
+  }
+  Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[test]
+  fn test_result_references() {
+    let mut results = Results::default();
+    results.push(&json!({"items": [{"name": "a"}, {"name": "b"}]}));
+    results.push(&json!({"id": "7f3c"}));
+    results.push(&Value::Null);
+
+    assert_eq!(results.get("_.id"), Some(Some(&json!("7f3c"))));
+    assert_eq!(results.get("1"), results.get("_"));
+    assert_eq!(results.get("2.items[1].name"), Some(Some(&json!("b"))));
+    assert_eq!(results.get("2.items.0.name"), Some(Some(&json!("a"))));
+    assert_eq!(results.get("_.nope"), Some(None));
+    assert_eq!(results.get("3"), Some(None));
+    assert_eq!(results.get("0"), None);
+    assert_eq!(results.get("HOME"), None);
+  }
+
+  #[test]
+  fn test_ring_keeps_recent_results() {
+    let mut results = Results::default();
+    for i in 0..CAPACITY + 5 {
+      results.push(&json!(i));
+    }
+    assert_eq!(results.get("1"), Some(Some(&json!(CAPACITY + 4))));
+    assert_eq!(results.get(&CAPACITY.to_string()), Some(Some(&json!(5))));
+    assert_eq!(results.get(&(CAPACITY + 1).to_string()), Some(None));
+  }
+}
@@ -1,50 +1,251 @@
+use anyhow::Context;
 use colored::Colorize;
-use futures::FutureExt;
 use reedline::{ExternalPrinter, Signal};
 use serde_json::Value;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::env;
-use std::panic::AssertUnwindSafe;
+use std::time::{Duration, Instant};
 use tracing::{error, info};
 
 use crate::Scripts;
-use crate::command_list::CommandList;
+use crate::command_tree::CommandTree;
+use crate::context::CommandContext;
 use crate::error::{Error, ToTrace};
+use crate::interrupt;
+use crate::output::OutputFormat;
+use crate::panic_hook;
+use crate::process;
+use crate::redirect::Redirect;
+use crate::results::Results;
+use crate::script::{self, OnError};
 use crate::shell::Callable;
-use crate::tokenizer::IntoArgs;
+use crate::tokenizer::{self, IntoArgs, Operator, Token};
 
 pub trait LineReader {
   fn read_line(&mut self) -> anyhow::Result<Signal>;
   fn external_printer(&self) -> Option<ExternalPrinter<String>>;
+
+  /// Called once before the loop starts with every registered command, for
+  /// readers that offer completion.
+  fn set_command_tree(&mut self, _tree: CommandTree) {}
+
+  /// False for readers fed by a script or pipe. The loop then keeps going
+  /// after a failure but exits with the status of the first one.
+  fn is_interactive(&self) -> bool {
+    true
+  }
+
+  /// Called after each line runs, for histories that record how it went.
+  fn command_finished(&mut self, _exit_status: i32, _duration: Duration) {}
+}
+
+/// How failed commands are reported.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorOutput {
+  /// Prints the message, its sources and the filtered backtrace to stderr.
+  #[default]
+  Pretty,
+  /// Emits a `tracing` error event with the trace serialized as
+  /// `exception.trace_json`, for apps that collect logs.
+  Tracing,
+}
+
+/// Settings that `Shell` threads through to the run loop.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RunOptions {
+  pub error_output: ErrorOutput,
+  pub output: OutputFormat,
+}
+
+/// State that builtins can change while the shell runs.
+struct Session {
+  output: OutputFormat,
+  error_output: ErrorOutput,
+  /// Ask for missing required arguments instead of failing. On in the REPL,
+  /// opt-in with `--prompt` on the command line.
+  prompt: bool,
+  /// Set with `set name value`, expanded as `$name`.
+  variables: BTreeMap<String, String>,
+  /// `$?`
+  last_status: i32,
+  /// `$_`, `$1`, `$2.items[0].id`, ...
+  results: Results,
+}
+
+impl Session {
+  fn new(options: &RunOptions) -> Self {
+    Session {
+      output: options.output,
+      error_output: options.error_output,
+      prompt: false,
+      variables: BTreeMap::new(),
+      last_status: 0,
+      results: Results::default(),
+    }
+  }
+
+  /// The value of `$name`: `$?`, then results like `$_.id`, then variables
+  /// from `set`, then the environment. A result that doesn't exist, or has
+  /// nothing at the path, is an error rather than an empty value.
+  fn variable(&self, name: &str) -> crate::Result<Option<String>> {
+    if name == "?" {
+      return Ok(Some(self.last_status.to_string()));
+    }
+    if let Some(value) = self.results.get(name) {
+      let Some(value) = value else {
+        let head = name.split(['.', '[']).next().unwrap_or(name);
+        let reason = match self.results.get(head) {
+          Some(Some(_)) => "the result has nothing at that path",
+          _ => "there's no such result",
+        };
+        return Err(Error::InvalidArgument {
+          name: format!("${name}"),
+          reason: reason.to_string(),
+        });
+      };
+      return Ok(Some(match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+      }));
+    }
+    Ok(
+      self
+        .variables
+        .get(name)
+        .cloned()
+        .or_else(|| env::var(name).ok()),
+    )
+  }
+
+  /// Remembers how a line went for `$?` and `$_`.
+  fn record(&mut self, result: &crate::Result<Value>) {
+    match result {
+      Ok(value) => {
+        self.last_status = 0;
+        self.results.push(value);
+      }
+      Err(e) => self.last_status = e.exit_code(),
+    }
+  }
+
+  fn print(&self, value: &Value) {
+    if let Some(rendered) = self.output.render(value) {
+      println!("{rendered}");
+    }
+  }
+
+  /// Pulls options that apply to the whole invocation off the front of
+  /// argv, eg. `--output table` or `--prompt`.
+  fn take_global_options(&mut self, argv: &mut Vec<String>) -> crate::Result<()> {
+    while let Some(first) = argv.first() {
+      if first == "--prompt" {
+        self.prompt = true;
+        argv.remove(0);
+        continue;
+      }
+      let format = match first.strip_prefix("--output") {
+        Some("") if argv.len() > 1 => argv.remove(1),
+        Some(rest) if rest.starts_with('=') => rest[1..].to_string(),
+        Some("") => return Err(Error::ArgParseError("--output needs a value".to_string())),
+        _ => break,
+      };
+      self.output = parse_output(&format)?;
+      argv.remove(0);
+    }
+    Ok(())
+  }
+
+  /// Runs a builtin that changes the session, if `argv` is one.
+  fn builtin(&mut self, argv: &[String]) -> Option<crate::Result<()>> {
+    match argv {
+      [name] if name == "output" => {
+        println!("{}", self.output);
+        Some(Ok(()))
+      }
+      [name, format] if name == "output" => Some(parse_output(format).map(|format| {
+        self.output = format;
+      })),
+      [name] if name == "set" => {
+        for (name, value) in &self.variables {
+          println!("{name}={value}");
+        }
+        Some(Ok(()))
+      }
+      [name, variable, value] if name == "set" => Some(if is_variable_name(variable) {
+        self.variables.insert(variable.clone(), value.clone());
+        Ok(())
+      } else {
+        Err(Error::InvalidArgument {
+          name: variable.clone(),
+          reason: "variable names are letters, digits and _, and start with a letter".to_string(),
+        })
+      }),
+      [name, ..] if name == "set" => Some(Err(Error::ArgParseError(
+        "usage: set <name> <value>".to_string(),
+      ))),
+      _ => None,
+    }
+  }
+}
+
+/// Names can't start with a digit or be `_`, those are results (`$1`, `$_`).
+fn is_variable_name(name: &str) -> bool {
+  name != "_"
+    && name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_output(format: &str) -> crate::Result<OutputFormat> {
+  format.parse().map_err(|reason| Error::InvalidArgument {
+    name: "--output".to_string(),
+    reason,
+  })
 }
 
 pub async fn run<Info>(
   scripts: Scripts<Info>,
-  subcommands: HashMap<String, Box<dyn Callable>>,
   line_reader: &mut (impl LineReader + ?Sized),
+  options: &RunOptions,
 ) where
   Info: Clone,
 {
-  if let Err(e) = run_once_or_loop(&scripts, subcommands, line_reader).await {
-    let panic_with_trace_ser =
-      serde_json::to_string(&e.to_trace()).expect("trace couldn't serialize");
-    error!(
-      exception.trace_json = panic_with_trace_ser,
-      "Command Failed"
-    );
+  interrupt::install_handler();
+  if let Err(e) = run_once_or_loop(&scripts, line_reader, options).await {
+    report(&e, options.error_output);
+    std::process::exit(e.exit_code());
+  }
+}
+
+fn report(e: &Error, error_output: ErrorOutput) {
+  // A command that chose its own exit code may have nothing else to say
+  if matches!(e, Error::Exit { message, .. } if message.is_empty()) {
+    return;
+  }
+  match error_output {
+    ErrorOutput::Pretty => eprintln!("{}", e.to_trace().render()),
+    ErrorOutput::Tracing => {
+      let panic_with_trace_ser =
+        serde_json::to_string(&e.to_trace()).expect("trace couldn't serialize");
+      error!(
+        exception.trace_json = panic_with_trace_ser,
+        "Command Failed"
+      );
+    }
   }
 }
 
 async fn run_once_or_loop<Info>(
   scripts: &Scripts<Info>,
-  subcommands: HashMap<String, Box<dyn Callable>>,
   line_reader: &mut (impl LineReader + ?Sized),
+  options: &RunOptions,
 ) -> Result<(), Error>
 where
   Info: Clone,
 {
   let mut argv: Vec<String> = env::args().collect();
-  if argv.len() > 1 {
+  let interactive = line_reader.is_interactive();
+  if argv.len() > 1 || !interactive {
     // manually set up the printer because we're not using reedline
     if let Some(external_printer) = line_reader.external_printer() {
       let rx = external_printer.receiver().clone();
@@ -54,57 +255,90 @@ where
         }
       });
     }
+  }
 
+  if argv.len() > 1 {
     argv.remove(0);
+    let mut session = Session::new(options);
+    session.take_global_options(&mut argv)?;
+    if argv.is_empty() {
+      return Err(Error::MissingSubcommand(scripts.names().join(", ")));
+    }
 
-    // In CLI mode, catch panics and exit with error code
-    let result = AssertUnwindSafe(exec(&scripts, &subcommands, argv))
-      .catch_unwind()
-      .await;
-
-    match result {
-      Ok(Ok(_)) => return Ok(()),
-      Ok(Err(e)) => return Err(e),
-      Err(_) => {
-        // A panic occurred - exit with error code
-        std::process::exit(1);
-      }
+    // `app --script runbook.mysh`, or a `#!/path/to/app --script` shebang
+    if argv[0] == "--script" {
+      let (path, on_error) = script::parse_source_args(&argv[1..])?;
+      let run = async {
+        run_script(scripts, &mut session, &path, on_error).await?;
+        Ok(Value::Null)
+      };
+      panic_hook::catch_panics(interrupt::cancellable(run)).await?;
+      return Ok(());
     }
+
+    // In CLI mode, panics are reported and exit with an error code
+    let prompt = session.prompt;
+    let run = async move {
+      let ctx = CommandContext::current().with_prompt(prompt);
+      ctx.scope(exec(scripts, argv)).await
+    };
+    let value = panic_hook::catch_panics(interrupt::cancellable(run)).await?;
+    session.print(&value);
+    return Ok(());
   }
 
   // println!("argv: {:?}", argv);
 
+  line_reader.set_command_tree(command_tree(scripts));
+  let mut session = Session::new(options);
+  session.prompt = interactive;
+  let mut first_failure: Option<i32> = None;
+
   loop {
     let sig = line_reader.read_line();
 
     match sig {
       Ok(Signal::Success(buffer)) => {
         let line = buffer.to_string();
-        if line.len() == 0 {
+        if line.trim().is_empty() {
           continue;
         }
 
-        let argv = line
-          .try_into_args()
-          .map_err(|e| Error::ArgParseError(e.to_string()))?;
-
-        // In interactive mode, catch panics and log them but continue
-        let result = AssertUnwindSafe(exec(&scripts, &subcommands, argv))
-          .catch_unwind()
-          .await;
+        // In interactive mode, panics are reported like errors and the loop
+        // continues
+        let started = Instant::now();
+        // `a; b` carries on after `a` fails, but the line still failed
+        let mut line_failure = None;
+        let mut on_failure = |e: Error| {
+          report(&e, options.error_output);
+          line_failure.get_or_insert(e.exit_code());
+          Ok(())
+        };
+        let result = panic_hook::catch_panics(interrupt::cancellable(eval_line(
+          scripts,
+          &mut session,
+          &line,
+          &mut on_failure,
+        )))
+        .await;
+        let exit_status = match &result {
+          Ok(_) => line_failure.unwrap_or(0),
+          Err(e) => e.exit_code(),
+        };
+        line_reader.command_finished(exit_status, started.elapsed());
+        if let Some(code) = line_failure {
+          first_failure.get_or_insert(code);
+        }
 
         match result {
-          Ok(Ok(_)) => {}
-          Ok(Err(e)) => {
-            let panic_with_trace_ser =
-              serde_json::to_string(&e.to_trace()).expect("trace couldn't serialize");
-            error!(
-              exception.trace_json = panic_with_trace_ser,
-              "Command Failed"
-            );
+          Ok(value) => session.print(&value),
+          Err(Error::Cancelled) => {
+            eprintln!("{}", "Cancelled".yellow());
+            first_failure.get_or_insert(Error::Cancelled.exit_code());
           }
-          Err(_) => {
-            error!("Command panicked!");
+          Err(e) => {
+            report(&e, options.error_output);
+            first_failure.get_or_insert(e.exit_code());
           }
         }
       }
@@ -118,56 +352,522 @@ where
     }
   }
 
-  return Ok(());
+  // Each failure was already reported as it happened
+  match first_failure {
+    Some(code) if !interactive => Err(Error::exit(code, "")),
+    _ => Ok(()),
+  }
+}
+
+/// Runs a line of commands joined by `;`, `&&` and `||`, expanding each
+/// command's variables just before it runs. Every result but the last one is
+/// printed as it comes in, the last one is returned. A line starting with `!`
+/// goes to the system shell instead.
+///
+/// A failure that a following `||` doesn't handle, as in `a; b`, goes to
+/// `on_failure`, which reports it and returns `Ok` to carry on with the line
+/// or returns an error to stop it there.
+async fn eval_line<Info: Clone>(
+  scripts: &Scripts<Info>,
+  session: &mut Session,
+  line: &str,
+  on_failure: &mut dyn FnMut(Error) -> crate::Result<()>,
+) -> crate::Result<Value> {
+  // `!cmd` hands the whole line to the system shell
+  if let Some(command) = line.trim_start().strip_prefix('!') {
+    let result = process::run_system_shell(command).await;
+    session.record(&result);
+    return result;
+  }
+
+  let mut last: Option<crate::Result<Value>> = None;
+  for (operator, command) in parse_chain(line)? {
+    if !should_run(operator, last.as_ref()) {
+      continue;
+    }
+    match last.take() {
+      Some(Ok(value)) => session.print(&value),
+      Some(Err(e)) if operator == Some(Operator::Or) => report(&e, session.error_output),
+      Some(Err(e)) => on_failure(e)?,
+      None => {}
+    }
+    let result = match expand(session, command) {
+      Ok(tokens) => eval(scripts, session, tokens).await,
+      Err(e) => {
+        let result = Err(e);
+        session.record(&result);
+        result
+      }
+    };
+    // Ctrl-C stops the whole line
+    if matches!(result, Err(Error::Cancelled)) {
+      return result;
+    }
+    last = Some(result);
+  }
+  last.unwrap_or(Ok(Value::Null))
+}
+
+/// Splits one command into words, replacing its variables. The first
+/// variable that can't be resolved fails the command.
+fn expand(session: &Session, command: &str) -> crate::Result<Vec<Token>> {
+  let unresolved = RefCell::new(None);
+  let tokens = command
+    .try_expand(&|name| {
+      session.variable(name).unwrap_or_else(|e| {
+        unresolved.borrow_mut().get_or_insert(e);
+        None
+      })
+    })
+    .map_err(|e| Error::ArgParseError(e.to_string()))?;
+  match unresolved.into_inner() {
+    Some(e) => Err(e),
+    None => Ok(tokens),
+  }
+}
+
+/// Splits a line at `;`, `&&` and `||`. A trailing `;` is allowed, any
+/// other missing command is an error.
+fn parse_chain(line: &str) -> crate::Result<Vec<(Option<Operator>, &str)>> {
+  let mut chain = tokenizer::split_chain(line).map_err(|e| Error::ArgParseError(e.to_string()))?;
+  if chain.len() > 1
+    && matches!(chain.last(), Some((Some(Operator::Then), command)) if command.trim().is_empty())
+  {
+    chain.pop();
+  }
+  for (i, (operator, command)) in chain.iter().enumerate() {
+    // A blank line on its own is left to `exec_pipeline`
+    if chain.len() > 1 && command.trim().is_empty() {
+      let operator = operator.or(chain.get(i + 1).and_then(|(op, _)| *op));
+      return Err(Error::ArgParseError(format!(
+        "missing command before or after {}",
+        operator.map_or("", |op| op.as_str())
+      )));
+    }
+  }
+  Ok(chain)
+}
+
+/// `a && b` only runs `b` if `a` succeeded, `a || b` only if it failed.
+fn should_run(operator: Option<Operator>, last: Option<&crate::Result<Value>>) -> bool {
+  !matches!(
+    (operator, last),
+    (Some(Operator::And), Some(Err(_))) | (Some(Operator::Or), Some(Ok(_)))
+  )
+}
+
+/// Runs one command or pipeline the way the REPL does: session builtins
+/// such as `output` and `source` first, then commands.
+async fn eval<Info: Clone>(
+  scripts: &Scripts<Info>,
+  session: &mut Session,
+  mut tokens: Vec<Token>,
+) -> crate::Result<Value> {
+  let redirect = Redirect::take(&mut tokens)?;
+  let result = eval_tokens(scripts, session, tokens).await;
+  session.record(&result);
+  match (redirect, result) {
+    (Some(redirect), Ok(value)) => redirect.write(&value).map(|_| Value::Null),
+    (_, result) => result,
+  }
+}
+
+async fn eval_tokens<Info: Clone>(
+  scripts: &Scripts<Info>,
+  session: &mut Session,
+  tokens: Vec<Token>,
+) -> crate::Result<Value> {
+  let words: Option<Vec<String>> = tokens
+    .iter()
+    .map(|token| match token {
+      Token::Word(word) => Some(word.clone()),
+      Token::Operator(_) => None,
+    })
+    .collect();
+  // Commands registered with the same name as a builtin replace it
+  let words = words.filter(|argv| {
+    !argv
+      .first()
+      .is_some_and(|name| is_registered(scripts, name))
+  });
+  if let Some(argv) = words {
+    if let Some(result) = session.builtin(&argv) {
+      return result.map(|_| Value::Null);
+    }
+    if argv.first().is_some_and(|name| name == "source") {
+      let (path, on_error) = script::parse_source_args(&argv[1..])?;
+      Box::pin(run_script(scripts, session, &path, on_error)).await?;
+      return Ok(Value::Null);
+    }
+  }
+  let ctx = CommandContext::current().with_prompt(session.prompt);
+  ctx.scope(exec_pipeline(scripts, tokens)).await
+}
+
+/// Runs each command of a script file as if it was typed at the prompt,
+/// printing each result.
+async fn run_script<Info: Clone>(
+  scripts: &Scripts<Info>,
+  session: &mut Session,
+  path: &str,
+  on_error: OnError,
+) -> crate::Result<()> {
+  let text = std::fs::read_to_string(path).with_context(|| format!("couldn't read {path}"))?;
+  let commands = script::parse_script(&text);
+
+  let mut failed: Option<(usize, i32)> = None;
+  let mut count_failure = |code: i32| {
+    let (count, first) = failed.unwrap_or((0, code));
+    failed = Some((count + 1, first));
+  };
+  for command in &commands {
+    let in_script = |e: Error| Error::Script {
+      path: path.to_string(),
+      line: command.number,
+      source: Box::new(e),
+    };
+    // A failure before the end of a chain, as in `a; b`
+    let mut chain_failure = None;
+    let error_output = session.error_output;
+    let mut on_failure = |e: Error| match on_error {
+      OnError::Stop => Err(e),
+      OnError::Continue => {
+        let error = in_script(e);
+        report(&error, error_output);
+        chain_failure.get_or_insert(error.exit_code());
+        Ok(())
+      }
+    };
+    let result = eval_line(scripts, session, &command.text, &mut on_failure).await;
+    let error = match result {
+      Ok(value) => {
+        session.print(&value);
+        if let Some(code) = chain_failure {
+          count_failure(code);
+        }
+        continue;
+      }
+      Err(Error::Cancelled) => return Err(Error::Cancelled),
+      Err(e) => in_script(e),
+    };
+    match on_error {
+      OnError::Stop => return Err(error),
+      OnError::Continue => {
+        report(&error, session.error_output);
+        count_failure(chain_failure.unwrap_or(error.exit_code()));
+      }
+    }
+  }
+
+  match failed {
+    // Each failure was already reported
+    Some((count, code)) => Err(Error::exit(
+      code,
+      format!("{count} of {} commands in {path} failed", commands.len()),
+    )),
+    None => Ok(()),
+  }
+}
+
+/// Tokenizes and runs one line of input. With `a; b`, `a && b` or `a || b`
+/// the result of the last command that ran is returned.
+pub(crate) async fn run_line<Info: Clone>(
+  scripts: &Scripts<Info>,
+  line: &str,
+) -> crate::Result<Value> {
+  let mut last = None;
+  for (operator, command) in parse_chain(line)? {
+    if !should_run(operator, last.as_ref()) {
+      continue;
+    }
+    let mut tokens = command
+      .try_into_tokens()
+      .map_err(|e| Error::ArgParseError(e.to_string()))?;
+    let redirect = Redirect::take(&mut tokens)?;
+    let result = exec_pipeline(scripts, tokens).await;
+    last = Some(match (redirect, result) {
+      (Some(redirect), Ok(value)) => redirect.write(&value).map(|_| Value::Null),
+      (_, result) => result,
+    });
+  }
+  last.unwrap_or(Ok(Value::Null))
 }
 
-async fn exec<Info: Clone>(
+/// Runs `a | b | c`, handing each command's result to the next one as
+/// input, and returns the last result.
+async fn exec_pipeline<Info: Clone>(
   scripts: &Scripts<Info>,
-  subcommands: &HashMap<String, Box<dyn Callable>>,
-  argv: Vec<String>,
+  tokens: Vec<Token>,
 ) -> crate::Result<Value> {
+  if tokens.is_empty() {
+    return Err(Error::MissingSubcommand(scripts.names().join(", ")));
+  }
+
+  let mut commands = vec![vec![]];
+  for token in tokens {
+    match token {
+      Token::Word(word) => commands.last_mut().expect("").push(word),
+      Token::Operator(Operator::Pipe) => commands.push(vec![]),
+      // Split off by `parse_chain` before getting here
+      Token::Operator(operator) => {
+        return Err(Error::ArgParseError(format!(
+          "unexpected {}",
+          operator.as_str()
+        )));
+      }
+    }
+  }
+  if commands.iter().any(Vec::is_empty) {
+    return Err(Error::ArgParseError(
+      "missing command before or after |".to_string(),
+    ));
+  }
+
+  let mut input = None;
+  for argv in commands {
+    let ctx = CommandContext::current().with_input(input.take());
+    input = Some(ctx.scope(exec(scripts, argv)).await?);
+  }
+  Ok(input.unwrap_or(Value::Null))
+}
+
+async fn exec<Info: Clone>(scripts: &Scripts<Info>, argv: Vec<String>) -> crate::Result<Value> {
   let name = &argv.get(0).expect("").clone();
-  if name == "help" {
+  if name == "help" && !is_registered(scripts, name) {
     let include_args = argv.iter().any(|s| s == "--args");
-    let command = argv.iter().find(|a| *a != "--args" && *a != "help");
-    let Some(help_arg) = command else {
-      print_help(&scripts.commands, subcommands, include_args);
+    let path: Vec<String> = argv
+      .iter()
+      .skip(1)
+      .filter(|a| *a != "--args")
+      .cloned()
+      .collect();
+    let Some(help_arg) = path.first() else {
+      print_help(scripts, include_args);
       return Ok(().into());
     };
 
-    let command = scripts
-      .commands
-      .find_command(help_arg)
-      .ok_or(Error::CommandNotFound(help_arg.clone()))?;
+    if let Some(command) = scripts.commands.find_command(help_arg) {
+      command.print_help();
+      return Ok(().into());
+    }
 
-    command.print_help();
-    return Ok(().into());
+    // `help cloud db` is the same as `cloud db --help`
+    let subcommand = scripts
+      .subcommands
+      .get(help_arg)
+      .ok_or(Error::CommandNotFound(help_arg.clone()))?;
+    let mut argv = path.clone();
+    argv.push("--help".to_string());
+    if include_args {
+      argv.push("--args".to_string());
+    }
+    return subcommand.call_with_argv(argv)?.await;
   }
 
   if let Some(command) = scripts.commands.find_command(&name) {
     return command.call_with_argv(scripts.info.clone(), argv)?.await;
   }
 
-  if let Some(subcommand) = subcommands.get(name) {
+  if let Some(subcommand) = scripts.subcommands.get(name) {
     return subcommand.call_with_argv(argv)?.await;
   }
 
+  if name != "--help" && name != "-h" {
+    return Err(Error::CommandNotFound(name.clone()));
+  }
+
   let include_args = argv.iter().any(|s| s == "--args");
-  print_help(&scripts.commands, subcommands, include_args);
+  print_help(scripts, include_args);
   Ok(().into())
 }
 
-pub fn print_help<Info: Clone>(
-  commands: &CommandList<Info>,
-  subcommands: &HashMap<String, Box<dyn Callable>>,
-  include_args: bool,
-) {
+fn command_tree<Info: Clone>(scripts: &Scripts<Info>) -> CommandTree {
+  let mut tree = scripts.command_tree();
+  let builtins = [
+    ("help", "Prints this message", vec!["--args".to_string()]),
+    (
+      "source",
+      "Runs each line of a script file",
+      vec!["-c, --continue: bool".to_string()],
+    ),
+    (
+      "output",
+      "Sets how results are printed: json, pretty, yaml, table or plain",
+      vec![],
+    ),
+    ("set", "Sets a variable, used as $name", vec![]),
+  ];
+  for (name, description, help) in builtins {
+    if tree.get(name).is_none() {
+      tree.add_command(name, description, &help);
+    }
+  }
+  tree
+}
+
+/// Whether `name` is a command or namespace the app registered.
+fn is_registered<Info: Clone>(scripts: &Scripts<Info>, name: &str) -> bool {
+  scripts.commands.find_command(name).is_some() || scripts.subcommands.contains_key(name)
+}
+
+pub fn print_help<Info: Clone>(scripts: &Scripts<Info>, include_args: bool) {
   println!("\nUsage: [name] [command]\n");
   println!("Commands:");
-  commands.print_help(0, include_args);
-  for (subcommand_name, subcommand) in subcommands {
-    println!("    {}", subcommand_name.bold());
-    subcommand.print_help(include_args);
-  }
+  scripts.commands.print_help(0, include_args);
+  scripts.print_subcommands(0, include_args);
   println!("");
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::CommandMetadata;
+  use std::future::Future;
+  use std::pin::Pin;
+
+  /// A command that returns its argv.
+  struct Echo(&'static str);
+
+  impl CommandMetadata<()> for Echo {
+    fn name(&self) -> &'static str {
+      self.0
+    }
+    fn description(&self) -> &'static str {
+      ""
+    }
+    fn long_description(&self) -> Option<&'static str> {
+      None
+    }
+    fn call_with_argv(
+      &self,
+      _: (),
+      argv: Vec<String>,
+    ) -> crate::Result<Pin<Box<dyn Future<Output = crate::Result<Value>>>>> {
+      Ok(Box::pin(async move { Ok(serde_json::json!(argv)) }))
+    }
+    fn help(&self) -> Vec<String> {
+      vec![]
+    }
+  }
+
+  fn new_session() -> Session {
+    Session::new(&RunOptions::default())
+  }
+
+  /// Runs a line that stops at the first failure.
+  async fn eval_stopping(
+    scripts: &Scripts<()>,
+    session: &mut Session,
+    line: &str,
+  ) -> crate::Result<Value> {
+    eval_line(scripts, session, line, &mut |e| Err(e)).await
+  }
+
+  #[tokio::test]
+  async fn test_registered_commands_replace_builtins() {
+    let scripts = Scripts::new(())
+      .add_command(Echo("set"))
+      .add_command(Echo("output"))
+      .add_command(Echo("source"));
+    let mut session = new_session();
+
+    let value = eval_stopping(&scripts, &mut session, "set name ana")
+      .await
+      .unwrap();
+    assert_eq!(value, serde_json::json!(["set", "name", "ana"]));
+    assert!(session.variables.is_empty());
+
+    let value = eval_stopping(&scripts, &mut session, "output table")
+      .await
+      .unwrap();
+    assert_eq!(value, serde_json::json!(["output", "table"]));
+    assert_eq!(session.output, OutputFormat::default());
+
+    let value = eval_stopping(&scripts, &mut session, "source x")
+      .await
+      .unwrap();
+    assert_eq!(value, serde_json::json!(["source", "x"]));
+
+    let tree = command_tree(&scripts);
+    assert_eq!(tree.get("set").unwrap().description, "");
+    assert!(tree.get("help").is_some());
+  }
+
+  #[tokio::test]
+  async fn test_unresolved_results_fail_the_command() {
+    let scripts = Scripts::new(()).add_command(Echo("echo"));
+    let mut session = new_session();
+    let unresolved = |result: crate::Result<Value>| match result {
+      Err(Error::InvalidArgument { name, .. }) => name,
+      result => panic!("expected an error, got {result:?}"),
+    };
+
+    let result = eval_stopping(&scripts, &mut session, "echo $_").await;
+    assert_eq!(unresolved(result), "$_");
+    assert_eq!(session.last_status, 2);
+
+    eval_stopping(&scripts, &mut session, "echo a")
+      .await
+      .unwrap();
+    let value = eval_stopping(&scripts, &mut session, "echo $_[1]")
+      .await
+      .unwrap();
+    assert_eq!(value, serde_json::json!(["echo", "a"]));
+
+    let result = eval_stopping(&scripts, &mut session, "echo $_.id").await;
+    assert_eq!(unresolved(result), "$_.id");
+    let result = eval_stopping(&scripts, &mut session, "echo $3").await;
+    assert_eq!(unresolved(result), "$3");
+    // The command after a failed expansion doesn't run with `&&`
+    let result = eval_stopping(&scripts, &mut session, "echo $4 && echo b").await;
+    assert_eq!(unresolved(result), "$4");
+  }
+
+  #[tokio::test]
+  async fn test_failures_within_a_chain() {
+    let scripts = Scripts::new(()).add_command(Echo("echo"));
+    let mut session = new_session();
+
+    let mut failures = vec![];
+    let value = eval_line(
+      &scripts,
+      &mut session,
+      "nope; echo a; nope && echo b; echo c; nope || echo d",
+      &mut |e| {
+        failures.push(e.exit_code());
+        Ok(())
+      },
+    )
+    .await
+    .unwrap();
+    assert_eq!(value, serde_json::json!(["echo", "d"]));
+    // `|| echo d` handled the last one
+    assert_eq!(failures, vec![2, 2]);
+
+    let result = eval_stopping(&scripts, &mut session, "nope; set x 1").await;
+    assert!(matches!(result, Err(Error::CommandNotFound(_))));
+    assert!(session.variables.is_empty());
+  }
+
+  #[tokio::test]
+  async fn test_scripts_count_failures_within_a_chain() {
+    let scripts = Scripts::new(()).add_command(Echo("echo"));
+    let path = std::env::temp_dir().join(format!("mysh-chain-{}.mysh", std::process::id()));
+    std::fs::write(&path, "nope; set x 1\necho a\n").unwrap();
+    let path = path.to_string_lossy().into_owned();
+
+    let mut session = new_session();
+    let result = run_script(&scripts, &mut session, &path, OnError::Stop).await;
+    assert!(matches!(result, Err(Error::Script { line: 1, .. })));
+    assert!(session.variables.is_empty());
+
+    session = new_session();
+    let result = run_script(&scripts, &mut session, &path, OnError::Continue).await;
+    match result {
+      Err(Error::Exit { code: 2, message }) => assert!(message.starts_with("1 of 2 commands")),
+      result => panic!("expected the script to fail, got {result:?}"),
+    }
+    assert_eq!(session.variables["x"], "1");
+    std::fs::remove_file(path).unwrap();
+  }
+}
@@ -0,0 +1,112 @@
+use crate::error::Error;
+
+/// What `source` and `--script` do when a command fails.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum OnError {
+  #[default]
+  Stop,
+  /// Reports the failure and carries on with the next command.
+  Continue,
+}
+
+/// A command read from a script, with the line it starts on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ScriptLine {
+  pub number: usize,
+  pub text: String,
+}
+
+/// Splits a script into commands. Blank lines and lines starting with `#`
+/// (including a shebang) are skipped, and a line ending in `\` continues on
+/// the next one.
+pub(crate) fn parse_script(text: &str) -> Vec<ScriptLine> {
+  let mut commands = vec![];
+  let mut pending: Option<ScriptLine> = None;
+  for (i, line) in text.lines().enumerate() {
+    let mut command = match pending.take() {
+      Some(command) => command,
+      None if line.trim().is_empty() || line.trim_start().starts_with('#') => continue,
+      None => ScriptLine {
+        number: i + 1,
+        text: String::new(),
+      },
+    };
+    match line.strip_suffix('\\') {
+      Some(line) => {
+        command.text.push_str(line);
+        pending = Some(command);
+      }
+      None => {
+        command.text.push_str(line);
+        commands.push(command);
+      }
+    }
+  }
+  // A trailing `\` on the last line just ends the command
+  commands.extend(pending);
+  commands
+}
+
+/// Parses `[--continue] <file>`, the arguments of both `source` and
+/// `--script`.
+pub(crate) fn parse_source_args(args: &[String]) -> crate::Result<(String, OnError)> {
+  let mut on_error = OnError::Stop;
+  let mut path = None;
+  for arg in args {
+    match arg.as_str() {
+      "--continue" | "-c" => on_error = OnError::Continue,
+      arg if path.is_none() => path = Some(arg.to_string()),
+      arg => return Err(Error::ArgParseError(format!("unexpected argument {arg}"))),
+    }
+  }
+  let path = path.ok_or_else(|| Error::ArgParseError("missing script file".to_string()))?;
+  Ok((path, on_error))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn line(number: usize, text: &str) -> ScriptLine {
+    ScriptLine {
+      number,
+      text: text.to_string(),
+    }
+  }
+
+  #[test]
+  fn test_parse_script() {
+    let script = "#!/usr/local/bin/app --script\n\
+                  # restart everything\n\
+                  \n\
+                  status print\n\
+                  \x20 # indented comment\n\
+                  deploy \\\n\
+                  \x20 --env prod \\\n\
+                  \x20 --force\n\
+                  echo done";
+    assert_eq!(
+      parse_script(script),
+      vec![
+        line(4, "status print"),
+        line(6, "deploy   --env prod   --force"),
+        line(9, "echo done"),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_parse_source_args() {
+    let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+    assert_eq!(
+      parse_source_args(&args(&["run.mysh"])).unwrap(),
+      ("run.mysh".to_string(), OnError::Stop)
+    );
+    assert_eq!(
+      parse_source_args(&args(&["--continue", "run.mysh"])).unwrap(),
+      ("run.mysh".to_string(), OnError::Continue)
+    );
+    assert!(parse_source_args(&args(&[])).is_err());
+    assert!(parse_source_args(&args(&["a", "b"])).is_err());
+  }
+}
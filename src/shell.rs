@@ -1,19 +1,33 @@
 use std::{
   borrow::Cow,
   collections::HashMap,
+  io::{BufRead, IsTerminal},
   sync::{Arc, RwLock},
+  time::Duration,
 };
 
 use crate::error::Error;
 use anyhow::Context;
+use colored::Colorize;
 use futures::Future;
 use reedline::{
-  DefaultPrompt, DefaultPromptSegment, ExternalPrinter, FileBackedHistory, Prompt,
-  PromptHistorySearchStatus, Reedline,
+  ColumnarMenu, DefaultPrompt, DefaultPromptSegment, Emacs, ExternalPrinter, KeyCode, KeyModifiers,
+  MenuBuilder, Prompt, PromptHistorySearchStatus, Reedline, ReedlineEvent, ReedlineMenu,
+  default_emacs_keybindings,
 };
 use serde_json::Value;
 
-use crate::{command_list::CommandList, command_metadata::CommandMetadata, run_loop::LineReader};
+use crate::{
+  command_list::CommandList,
+  command_metadata::CommandMetadata,
+  command_tree::CommandTree,
+  completion::ShellCompleter,
+  highlight::ShellHighlighter,
+  history::{HistoryConfig, RedactedHistory},
+  output::OutputFormat,
+  run_loop::{ErrorOutput, LineReader, RunOptions},
+  validation::{ShellValidator, is_incomplete},
+};
 
 pub trait Callable {
   fn call_with_argv(
@@ -21,7 +35,9 @@ pub trait Callable {
     argv: Vec<String>,
   ) -> crate::Result<std::pin::Pin<Box<dyn Future<Output = crate::Result<Value>>>>>;
 
-  fn print_help(&self, include_args: bool);
+  fn print_help(&self, level: usize, include_args: bool);
+
+  fn command_tree(&self) -> CommandTree;
 }
 
 pub struct Scripts<Info>
@@ -30,6 +46,7 @@ where
 {
   pub info: Info,
   pub commands: CommandList<Info>,
+  pub(crate) subcommands: HashMap<String, Box<dyn Callable>>,
 }
 
 impl<Info> Scripts<Info>
@@ -40,6 +57,7 @@ where
     Scripts {
       info,
       commands: CommandList::new(),
+      subcommands: HashMap::new(),
     }
   }
 
@@ -47,12 +65,14 @@ where
     Shell::new_with_root_scripts(self)
   }
 
+  /// Runs with the default settings. Use `to_shell` to change them, eg.
+  /// `scripts.to_shell().history(HistoryConfig::disabled()).run()`.
   pub async fn run(self) {
-    crate::run_loop::run(self, HashMap::new(), &mut DefaultLineReader::new()).await;
+    self.to_shell().run().await;
   }
 
   pub async fn run_with(self) {
-    crate::run_loop::run(self, HashMap::new(), &mut DefaultLineReader::new()).await;
+    self.to_shell().run().await;
   }
 
   pub fn add_command<C>(mut self, command: C) -> Self
@@ -63,21 +83,44 @@ where
     self
   }
 
-  pub async fn run_command(&self, command: &str) -> crate::Result<crate::json::Value> {
-    let argv = command
-      .split(" ")
-      .map(|s| s.to_string())
-      .collect::<Vec<String>>();
+  /// Nests `commands` under `namespace`, ie. `[namespace] [command]`. The
+  /// nested scripts can have subcommands of their own.
+  pub fn add_subcommand<SubcommandInfo>(
+    mut self,
+    namespace: &str,
+    commands: Scripts<SubcommandInfo>,
+  ) -> Self
+  where
+    SubcommandInfo: Clone + 'static,
+  {
+    self
+      .subcommands
+      .insert(namespace.to_string(), Box::new(commands));
+    self
+  }
 
-    let subcommand_name = argv
-      .get(0)
-      .ok_or_else(|| Error::MissingSubcommand(self.commands.names().join(", ")))?;
-    let subcommand = self
-      .commands
-      .find_command(&subcommand_name)
-      .ok_or(Error::NoSuchSubcommand)?;
+  /// Sorted names of the commands and namespaces directly in these scripts.
+  pub fn names(&self) -> Vec<String> {
+    let mut names = self.commands.names();
+    names.extend(self.subcommands.keys().cloned());
+    names.sort();
+    names
+  }
+
+  pub(crate) fn print_subcommands(&self, level: usize, include_args: bool) {
+    let mut names: Vec<&String> = self.subcommands.keys().collect();
+    names.sort();
+    for name in names {
+      self.commands.print_indent(level);
+      println!("{}", name.bold());
+      self.subcommands[name].print_help(level + 1, include_args);
+    }
+  }
 
-    Ok(subcommand.call_with_argv(self.info.clone(), argv)?.await?)
+  /// Runs a single command line, eg. `status print --verbose`, and returns the
+  /// command's result. Lines are tokenized and routed exactly like the REPL.
+  pub async fn run_command(&self, command: &str) -> crate::Result<crate::json::Value> {
+    crate::run_loop::run_line(self, command).await
   }
 }
 
@@ -86,6 +129,14 @@ impl<T: Clone> Callable for Scripts<T> {
     &self,
     argv: Vec<String>,
   ) -> crate::Result<std::pin::Pin<Box<dyn Future<Output = crate::Result<Value>>>>> {
+    // argv[0] is this namespace, route ["cloud", "db", ...] into "db"
+    let nested = argv
+      .get(1)
+      .and_then(|name| self.subcommands.get(name.as_str()));
+    if let Some(nested) = nested {
+      return nested.call_with_argv(argv[1..].to_vec());
+    }
+
     // Check if --help flag is present
     let has_help_flag = argv.iter().any(|arg| arg == "--help" || arg == "-h");
 
@@ -100,7 +151,7 @@ impl<T: Clone> Callable for Scripts<T> {
 
       if non_flag_args.len() == 1 {
         // Only the namespace, show help for all commands in this namespace
-        self.print_help(include_args);
+        self.print_help(1, include_args);
         return Ok(Box::pin(async { Ok(().into()) }));
       }
 
@@ -117,7 +168,7 @@ impl<T: Clone> Callable for Scripts<T> {
 
     let subcommand_name = argv
       .get(1)
-      .ok_or_else(|| Error::MissingSubcommand(self.commands.names().join(", ")))?;
+      .ok_or_else(|| Error::MissingSubcommand(self.names().join(", ")))?;
     let subcommand = self
       .commands
       .find_command(&subcommand_name)
@@ -127,8 +178,17 @@ impl<T: Clone> Callable for Scripts<T> {
     subcommand.call_with_argv(self.info.clone(), argv)
   }
 
-  fn print_help(&self, include_args: bool) {
-    self.commands.print_help(1, include_args);
+  fn print_help(&self, level: usize, include_args: bool) {
+    self.commands.print_help(level, include_args);
+    self.print_subcommands(level, include_args);
+  }
+
+  fn command_tree(&self) -> CommandTree {
+    let mut tree = self.commands.command_tree();
+    for (name, subcommand) in &self.subcommands {
+      tree.add_namespace(name, subcommand.command_tree());
+    }
+    tree
   }
 }
 
@@ -137,8 +197,9 @@ where
   Info: Clone,
 {
   root_scripts: Scripts<Info>,
-  subcommands: HashMap<String, Box<dyn Callable>>,
   linereader: Option<Box<dyn LineReader>>,
+  history: HistoryConfig,
+  options: RunOptions,
 }
 
 impl<Info> Shell<Info>
@@ -149,7 +210,8 @@ where
     Shell {
       root_scripts: Scripts::new(info),
       linereader: None,
-      subcommands: HashMap::new(),
+      history: HistoryConfig::default(),
+      options: RunOptions::default(),
     }
   }
 
@@ -157,7 +219,8 @@ where
     Shell {
       root_scripts,
       linereader: None,
-      subcommands: HashMap::new(),
+      history: HistoryConfig::default(),
+      options: RunOptions::default(),
     }
   }
 
@@ -169,6 +232,27 @@ where
     self
   }
 
+  /// Chooses how failed commands are reported. Defaults to printing them
+  /// to stderr.
+  pub fn error_output(mut self, error_output: ErrorOutput) -> Self {
+    self.options.error_output = error_output;
+    self
+  }
+
+  /// Where the default line reader keeps history. Ignored when a line reader
+  /// is set with `set_line_reader`.
+  pub fn history(mut self, history: HistoryConfig) -> Self {
+    self.history = history;
+    self
+  }
+
+  /// Chooses how command results are printed. Defaults to indented JSON;
+  /// `--output` on the command line and the `output` builtin override it.
+  pub fn output(mut self, output: OutputFormat) -> Self {
+    self.options.output = output;
+    self
+  }
+
   pub fn add_command<C>(mut self, command: C) -> Self
   where
     C: CommandMetadata<Info> + Sized + 'static,
@@ -185,19 +269,23 @@ where
   where
     SubcommandInfo: Clone + 'static,
   {
-    self
-      .subcommands
-      .insert(namespace.to_string(), Box::new(commands));
+    self.root_scripts = self.root_scripts.add_subcommand(namespace, commands);
     self
   }
 
+  /// Runs a single command line against this shell's commands and
+  /// namespaces, and returns the command's result. See `Scripts::run_command`.
+  pub async fn run_command(&self, line: &str) -> crate::Result<crate::json::Value> {
+    self.root_scripts.run_command(line).await
+  }
+
   pub async fn run(self) {
     crate::run_loop::run(
       self.root_scripts,
-      self.subcommands,
       &mut (*self
         .linereader
-        .unwrap_or_else(|| Box::new(DefaultLineReader::new()))),
+        .unwrap_or_else(|| default_line_reader(self.history, None))),
+      &self.options,
     )
     .await;
   }
@@ -205,10 +293,10 @@ where
   pub async fn run_with(self, external_printer: ExternalPrinter<String>) {
     crate::run_loop::run(
       self.root_scripts,
-      self.subcommands,
       &mut (*self
         .linereader
-        .unwrap_or_else(|| Box::new(DefaultLineReader::new_with(Some(external_printer))))),
+        .unwrap_or_else(|| default_line_reader(self.history, Some(external_printer)))),
+      &self.options,
     )
     .await;
   }
@@ -277,20 +365,59 @@ impl Prompt for PromptText {
 pub struct DefaultLineReader {
   pub(crate) reedline: Reedline,
   pub(crate) printer: Option<ExternalPrinter<String>>,
+  pub(crate) commands: Arc<RwLock<CommandTree>>,
+  records_context: bool,
   pub prompt: PromptText,
 }
 
 impl DefaultLineReader {
+  /// A reader with the default history, see `HistoryConfig::default`.
   pub fn new() -> Self {
     Self::new_with(None)
   }
 
+  /// A reader with the default history, see `with_history` to change it.
   pub fn new_with(external_printer: Option<ExternalPrinter<String>>) -> Self {
-    let history = Box::new(
-      FileBackedHistory::with_file(100, "history.txt".into())
-        .expect("Error configuring history with file"),
+    Self::with_history(HistoryConfig::default(), external_printer)
+  }
+
+  pub fn with_history(
+    history: HistoryConfig,
+    external_printer: Option<ExternalPrinter<String>>,
+  ) -> Self {
+    let commands = Arc::new(RwLock::new(CommandTree::new()));
+    // The history and reedline must agree on the session to filter by it
+    let session = history
+      .records_context()
+      .then(Reedline::create_history_session_id)
+      .flatten();
+
+    let completion_menu = Box::new(ColumnarMenu::default().with_name("completion_menu"));
+    let mut keybindings = default_emacs_keybindings();
+    keybindings.add_binding(
+      KeyModifiers::NONE,
+      KeyCode::Tab,
+      ReedlineEvent::UntilFound(vec![
+        ReedlineEvent::Menu("completion_menu".to_string()),
+        ReedlineEvent::MenuNext,
+      ]),
     );
-    let mut reedline = Reedline::create().with_history(history);
+
+    let mut reedline = Reedline::create()
+      .with_history(Box::new(RedactedHistory::new(
+        history.build(session),
+        commands.clone(),
+      )))
+      .with_completer(Box::new(ShellCompleter::new(commands.clone())))
+      .with_highlighter(Box::new(ShellHighlighter::new(commands.clone())))
+      .with_validator(Box::new(ShellValidator))
+      .with_quick_completions(true)
+      .with_partial_completions(true)
+      .with_menu(ReedlineMenu::EngineCompleter(completion_menu))
+      .with_edit_mode(Box::new(Emacs::new(keybindings)));
+    if session.is_some() {
+      reedline = reedline.with_history_session_id(session);
+    }
     reedline = if let Some(external_printer) = external_printer.clone() {
       reedline.with_external_printer(external_printer)
     } else {
@@ -299,6 +426,8 @@ impl DefaultLineReader {
     DefaultLineReader {
       reedline,
       printer: external_printer,
+      commands,
+      records_context: history.records_context(),
       prompt: PromptText::new(),
     }
   }
@@ -312,4 +441,141 @@ impl LineReader for DefaultLineReader {
   fn external_printer(&self) -> Option<ExternalPrinter<String>> {
     self.printer.clone()
   }
+
+  fn set_command_tree(&mut self, tree: CommandTree) {
+    *self.commands.write().expect("") = tree;
+  }
+
+  fn command_finished(&mut self, exit_status: i32, duration: Duration) {
+    if !self.records_context {
+      return;
+    }
+    let cwd = std::env::current_dir()
+      .ok()
+      .map(|cwd| cwd.to_string_lossy().into_owned());
+    let _ = self.reedline.update_last_command_context(&|mut item| {
+      #[cfg(feature = "sqlite")]
+      {
+        item.start_timestamp = chrono::Duration::from_std(duration)
+          .ok()
+          .map(|duration| chrono::Utc::now() - duration);
+      }
+      item.cwd = cwd.clone();
+      item.duration = Some(duration);
+      item.exit_status = Some(exit_status.into());
+      item
+    });
+  }
+}
+
+/// Reads one command per line from a piped stdin, eg. `cat cmds.txt | app`,
+/// with no prompt, editor or history.
+pub struct StdinLineReader {
+  printer: Option<ExternalPrinter<String>>,
+}
+
+impl StdinLineReader {
+  pub fn new() -> Self {
+    Self::new_with(None)
+  }
+
+  pub fn new_with(external_printer: Option<ExternalPrinter<String>>) -> Self {
+    StdinLineReader {
+      printer: external_printer,
+    }
+  }
+}
+
+impl Default for StdinLineReader {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl LineReader for StdinLineReader {
+  fn read_line(&mut self) -> anyhow::Result<reedline::Signal> {
+    let line = read_command(&mut std::io::stdin().lock()).context("couldn't read stdin")?;
+    Ok(match line {
+      Some(line) => reedline::Signal::Success(line),
+      None => reedline::Signal::CtrlD,
+    })
+  }
+
+  fn external_printer(&self) -> Option<ExternalPrinter<String>> {
+    self.printer.clone()
+  }
+
+  fn is_interactive(&self) -> bool {
+    false
+  }
+}
+
+/// Reads one command, carrying on to the next lines while a quote is open or
+/// the line ends in `\`, like the prompt does. `None` at the end of input.
+fn read_command(input: &mut impl BufRead) -> std::io::Result<Option<String>> {
+  let mut command = String::new();
+  loop {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+      // An unfinished command is still run, to report what's wrong with it
+      command.pop();
+      return Ok((!command.is_empty()).then_some(command));
+    }
+    command.push_str(line.trim_end_matches(['\n', '\r']));
+    if !is_incomplete(&command) {
+      return Ok(Some(command));
+    }
+    command.push('\n');
+  }
+}
+
+/// Reedline when someone is typing, plain lines when stdin is piped.
+fn default_line_reader(
+  history: HistoryConfig,
+  external_printer: Option<ExternalPrinter<String>>,
+) -> Box<dyn LineReader> {
+  if std::io::stdin().is_terminal() {
+    Box::new(DefaultLineReader::with_history(history, external_printer))
+  } else {
+    Box::new(StdinLineReader::new_with(external_printer))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_read_command_continues_lines() {
+    let mut input = "a 'one\ntwo'\r\nb \\\n  c\nd\ne 'open\n".as_bytes();
+    assert_eq!(read_command(&mut input).unwrap().unwrap(), "a 'one\ntwo'");
+    assert_eq!(read_command(&mut input).unwrap().unwrap(), "b \\\n  c");
+    assert_eq!(read_command(&mut input).unwrap().unwrap(), "d");
+    assert_eq!(read_command(&mut input).unwrap().unwrap(), "e 'open");
+    assert_eq!(read_command(&mut input).unwrap(), None);
+  }
+
+  #[test]
+  fn test_reader_keeps_shell_history() {
+    let dir = std::env::temp_dir().join(format!("mysh-shell-history-{}", std::process::id()));
+    let path = dir.join("history.txt");
+    let save = |history: HistoryConfig| {
+      let mut reader = DefaultLineReader::with_history(history, None);
+      let history = reader.reedline.history_mut();
+      history
+        .save(reedline::HistoryItem::from_command_line("login --user ana"))
+        .unwrap();
+      history.sync().unwrap();
+      history.count_all().unwrap()
+    };
+
+    let shell = Shell::new(()).history(HistoryConfig::file(&path));
+    assert_eq!(save(shell.history), 1);
+    assert!(path.exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // Nothing sticks when the shell's history is disabled
+    let shell = Shell::new(()).history(HistoryConfig::disabled());
+    assert_eq!(save(shell.history), 0);
+  }
 }
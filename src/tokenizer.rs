@@ -1,5 +1,8 @@
 use core::mem;
 use std::fmt::{Display, Formatter};
+use std::iter::Peekable;
+use std::ops::Range;
+use std::str::Chars;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct ParseError;
@@ -12,6 +15,106 @@ impl Display for ParseError {
 
 impl std::error::Error for ParseError {}
 
+/// A word, or an unquoted operator that separates commands.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Token {
+  Word(String),
+  Operator(Operator),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Operator {
+  /// `|`, feeds the previous command's result into the next one.
+  Pipe,
+  /// `;`, runs the next command whatever happened.
+  Then,
+  /// `&&`, runs the next command if the previous one succeeded.
+  And,
+  /// `||`, runs the next command if the previous one failed.
+  Or,
+  /// `>`, writes the result to a file instead of printing it.
+  Write,
+  /// `>>`, appends the result to a file as one line of JSON.
+  Append,
+}
+
+impl Operator {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Operator::Pipe => "|",
+      Operator::Then => ";",
+      Operator::And => "&&",
+      Operator::Or => "||",
+      Operator::Write => ">",
+      Operator::Append => ">>",
+    }
+  }
+}
+
+/// The characters of a line, with how many bytes of it have been read.
+#[derive(Clone)]
+struct Cursor<'a> {
+  chars: Peekable<Chars<'a>>,
+  offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+  fn new(line: &'a str) -> Self {
+    Cursor {
+      chars: line.chars().peekable(),
+      offset: 0,
+    }
+  }
+
+  fn peek(&mut self) -> Option<&char> {
+    self.chars.peek()
+  }
+
+  fn next_if(&mut self, f: impl FnOnce(&char) -> bool) -> Option<char> {
+    if self.peek().is_some_and(f) {
+      self.next()
+    } else {
+      None
+    }
+  }
+
+  fn next_if_eq(&mut self, expected: &char) -> Option<char> {
+    self.next_if(|c| c == expected)
+  }
+}
+
+impl Iterator for Cursor<'_> {
+  type Item = char;
+
+  fn next(&mut self) -> Option<char> {
+    let c = self.chars.next()?;
+    self.offset += c.len_utf8();
+    Some(c)
+  }
+}
+
+/// A lone `&` is an ordinary character.
+fn is_operator(c: char, next: Option<&char>) -> bool {
+  matches!(c, '|' | ';' | '>') || (c == '&' && next == Some(&'&'))
+}
+
+/// Reads the rest of the operator that starts with `c`.
+fn read_operator(c: char, chars: &mut Cursor) -> Operator {
+  match c {
+    '|' if chars.next_if_eq(&'|').is_some() => Operator::Or,
+    '|' => Operator::Pipe,
+    ';' => Operator::Then,
+    '>' if chars.next_if_eq(&'>').is_some() => Operator::Append,
+    '>' => Operator::Write,
+    '&' => {
+      chars.next();
+      Operator::And
+    }
+    _ => unreachable!("not an operator: {c}"),
+  }
+}
+
+#[derive(PartialEq, Eq)]
 enum State {
   /// Within a delimiter.
   Delimiter,
@@ -29,107 +132,518 @@ enum State {
   DoubleQuotedBackslash,
 }
 
+/// Looks up the value of `$name`.
+pub(crate) type Variables<'a> = &'a dyn Fn(&str) -> Option<String>;
+
 pub(crate) trait IntoArgs {
-  fn try_into_args(&self) -> Result<Vec<String>, ParseError>;
+  /// Splits a line into words and operators. `$` is kept as it is.
+  fn try_into_tokens(&self) -> Result<Vec<Token>, ParseError>;
+
+  /// Like `try_into_tokens`, but replaces `$name` and `${name}` outside of
+  /// single quotes with their value, or nothing when they aren't set.
+  fn try_expand(&self, variables: Variables) -> Result<Vec<Token>, ParseError>;
 }
 
 impl<S: std::ops::Deref<Target = str>> IntoArgs for S {
-  fn try_into_args(&self) -> Result<Vec<String>, ParseError> {
+  fn try_into_tokens(&self) -> Result<Vec<Token>, ParseError> {
+    Ok(
+      tokenize(self, None, None)?
+        .into_iter()
+        .map(|(token, _)| token)
+        .collect(),
+    )
+  }
+
+  fn try_expand(&self, variables: Variables) -> Result<Vec<Token>, ParseError> {
+    Ok(
+      tokenize(self, Some(variables), None)?
+        .into_iter()
+        .map(|(token, _)| token)
+        .collect(),
+    )
+  }
+}
+
+/// Splits a line into tokens without expanding variables, each with where
+/// it is in the line.
+pub(crate) fn spans(line: &str) -> Result<Vec<(Token, Range<usize>)>, ParseError> {
+  tokenize(line, None, None)
+}
+
+/// Splits a line into the commands joined by `;`, `&&` and `||`, each with
+/// the operator before it. Variables aren't expanded, so that each command
+/// can see the results of the ones before it.
+pub(crate) fn split_chain(line: &str) -> Result<Vec<(Option<Operator>, &str)>, ParseError> {
+  let mut commands = vec![];
+  let mut start = 0;
+  let mut operator = None;
+  for (token, span) in spans(line)? {
+    if let Token::Operator(op @ (Operator::Then | Operator::And | Operator::Or)) = token {
+      commands.push((operator, &line[start..span.start]));
+      operator = Some(op);
+      start = span.end;
+    }
+  }
+  commands.push((operator, &line[start..]));
+  Ok(commands)
+}
+
+/// Reads the name after a `$`: `?`, an identifier such as `_` or `HOME`, or
+/// anything in `{}`. Returns `None`, having consumed nothing, if there's no
+/// name.
+fn read_variable(chars: &mut Cursor) -> Option<String> {
+  match chars.peek() {
+    Some('?') => {
+      chars.next();
+      Some("?".to_string())
+    }
+    Some('{') => {
+      // Only consume the braces if they close
+      let mut ahead = chars.clone();
+      ahead.next();
+      let mut name = String::new();
+      loop {
+        match ahead.next() {
+          Some('}') => break,
+          Some(c) => name.push(c),
+          None => return None,
+        }
+      }
+      if name.is_empty() {
+        return None;
+      }
+      *chars = ahead;
+      Some(name)
+    }
+    Some(c) if c.is_ascii_alphanumeric() || *c == '_' => {
+      let mut name = String::new();
+      while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+        name.push(c);
+      }
+      // Results can be followed by a path, eg. `$_.items[0].id`
+      if name == "_" || name.chars().all(|c| c.is_ascii_digit()) {
+        while let Some(part) = read_path_part(chars) {
+          name.push_str(&part);
+        }
+      }
+      Some(name)
+    }
+    _ => None,
+  }
+}
+
+/// Reads one `.field` or `[index]`, consuming nothing if it isn't one.
+fn read_path_part(chars: &mut Cursor) -> Option<String> {
+  let mut ahead = chars.clone();
+  let mut part = String::from(ahead.next()?);
+  let is_name = |c: &char| c.is_ascii_alphanumeric() || *c == '_' || *c == '-';
+  let close = match part.as_str() {
+    "." => None,
+    "[" => Some(']'),
+    _ => return None,
+  };
+  while let Some(c) = ahead.next_if(|c| {
+    if close.is_some() {
+      c.is_ascii_digit()
+    } else {
+      is_name(c)
+    }
+  }) {
+    part.push(c);
+  }
+  if part.len() == 1 {
+    return None;
+  }
+  if let Some(close) = close {
+    part.push(ahead.next_if_eq(&close)?);
+  }
+  *chars = ahead;
+  Some(part)
+}
+
+/// Appends the value of the variable at `chars` to `word`, or a literal `$`
+/// if no name follows it or variables aren't being expanded.
+fn expand(word: &mut String, chars: &mut Cursor, variables: Option<Variables>) {
+  let Some(variables) = variables else {
+    word.push('$');
+    return;
+  };
+  match read_variable(chars) {
+    Some(name) => word.push_str(&variables(&name).unwrap_or_default()),
+    None => word.push('$'),
+  }
+}
+
+/// Ends the word being read. A word that's only empty because unquoted
+/// variables expanded to nothing is left out, like in `sh`, while `""` or
+/// `"$unset"` is still an empty word.
+fn end_word(
+  words: &mut Vec<(Token, Range<usize>)>,
+  word: &mut String,
+  quoted: &mut bool,
+  span: Range<usize>,
+) {
+  let quoted = mem::take(quoted);
+  if quoted || !word.is_empty() {
+    words.push((Token::Word(mem::take(word)), span));
+  }
+}
+
+/// How a part of the line was read, for highlighting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Class {
+  Space,
+  /// Unquoted word characters.
+  Plain,
+  /// Quotes and what's inside them.
+  Quoted,
+  /// A backslash and the character it escapes.
+  Escape,
+  /// `$name`, `${name}` or `$_.path`.
+  Variable,
+  Operator,
+}
+
+impl Class {
+  /// How `c` is read in `state`, given the character after it.
+  fn of(state: &State, c: char, next: Option<&char>) -> Class {
     use State::*;
+    match (state, c) {
+      (Delimiter | Unquoted, '\t' | ' ' | '\n') => Class::Space,
+      (Delimiter | Unquoted, '\'' | '\"') => Class::Quoted,
+      (Delimiter | Unquoted, '\\') | (Backslash | UnquotedBackslash, _) => Class::Escape,
+      (Delimiter | Unquoted | DoubleQuoted, '$') => Class::Variable,
+      (Delimiter | Unquoted, c) if is_operator(c, next) => Class::Operator,
+      (SingleQuoted | DoubleQuoted | DoubleQuotedBackslash, _) => Class::Quoted,
+      _ => Class::Plain,
+    }
+  }
+}
+
+/// Runs the tokenizer over `line` and returns how each part of it was read,
+/// including a line with an unclosed quote.
+pub(crate) fn classify(line: &str) -> Vec<(Range<usize>, Class)> {
+  let mut classes = vec![];
+  // Expanding to nothing is enough to find where variables are
+  let _ = tokenize(line, Some(&|_| None), Some(&mut classes));
+  classes
+}
 
-    let mut words = Vec::new();
-    let mut word = String::new();
-    let mut chars = self.chars();
-    let mut state = Delimiter;
-
-    loop {
-      let c = chars.next();
-      state = match state {
-        Delimiter => match c {
-          None => break,
-          Some('\'') => SingleQuoted,
-          Some('\"') => DoubleQuoted,
-          Some('\\') => Backslash,
-          Some('\t') | Some(' ') | Some('\n') => Delimiter,
-          Some(c) => {
-            word.push(c);
-            Unquoted
-          }
-        },
-        Backslash => match c {
-          None => {
-            word.push('\\');
-            words.push(mem::take(&mut word));
-            break;
-          }
-          Some('\n') => Delimiter,
-          Some(c) => {
-            word.push(c);
-            Unquoted
-          }
-        },
-        Unquoted => match c {
-          None => {
-            words.push(mem::take(&mut word));
-            break;
-          }
-          Some('\'') => SingleQuoted,
-          Some('\"') => DoubleQuoted,
-          Some('\\') => UnquotedBackslash,
-          Some('\t') | Some(' ') | Some('\n') => {
-            words.push(mem::take(&mut word));
-            Delimiter
-          }
-          Some(c) => {
-            word.push(c);
-            Unquoted
-          }
-        },
-        UnquotedBackslash => match c {
-          None => {
-            word.push('\\');
-            words.push(mem::take(&mut word));
-            break;
-          }
-          Some('\n') => Unquoted,
-          Some(c) => {
-            word.push(c);
-            Unquoted
-          }
-        },
-        SingleQuoted => match c {
-          None => return Err(ParseError),
-          Some('\'') => Unquoted,
-          Some(c) => {
-            word.push(c);
-            SingleQuoted
-          }
-        },
-        DoubleQuoted => match c {
-          None => return Err(ParseError),
-          Some('\"') => Unquoted,
-          Some('\\') => DoubleQuotedBackslash,
-          Some(c) => {
-            word.push(c);
-            DoubleQuoted
-          }
-        },
-        DoubleQuotedBackslash => match c {
-          None => return Err(ParseError),
-          Some('\n') => DoubleQuoted,
-          Some(c @ '$') | Some(c @ '`') | Some(c @ '"') | Some(c @ '\\') => {
-            word.push(c);
-            DoubleQuoted
-          }
-          Some(c) => {
-            word.push('\\');
-            word.push(c);
-            DoubleQuoted
-          }
-        },
+/// Splits `line` into tokens, each with the bytes of the line it was read
+/// from, quotes and escapes included. With `classes`, also
+/// records how each character (or run of characters, for operators and
+/// variables) was read.
+fn tokenize(
+  line: &str,
+  variables: Option<Variables>,
+  mut classes: Option<&mut Vec<(Range<usize>, Class)>>,
+) -> Result<Vec<(Token, Range<usize>)>, ParseError> {
+  use State::*;
+
+  let mut words = Vec::new();
+  let mut word = String::new();
+  // Whether the word has quotes, so it's kept even if empty
+  let mut quoted = false;
+  let mut word_start = 0;
+  let mut chars = Cursor::new(line);
+  let mut state = Delimiter;
+
+  loop {
+    let start = chars.offset;
+    // Whatever comes after a delimiter starts the next word
+    if state == Delimiter {
+      word_start = start;
+    }
+    let c = chars.next();
+    let class = match (&classes, c) {
+      (Some(_), Some(c)) => Some(Class::of(&state, c, chars.peek())),
+      _ => None,
+    };
+    state = match state {
+      Delimiter => match c {
+        None => break,
+        Some('\'') => {
+          quoted = true;
+          SingleQuoted
+        }
+        Some('\"') => {
+          quoted = true;
+          DoubleQuoted
+        }
+        Some('\\') => Backslash,
+        Some('\t') | Some(' ') | Some('\n') => Delimiter,
+        Some('$') => {
+          expand(&mut word, &mut chars, variables);
+          Unquoted
+        }
+        Some(c) if is_operator(c, chars.peek()) => {
+          let operator = read_operator(c, &mut chars);
+          words.push((Token::Operator(operator), start..chars.offset));
+          Delimiter
+        }
+        Some(c) => {
+          word.push(c);
+          Unquoted
+        }
+      },
+      Backslash => match c {
+        None => {
+          word.push('\\');
+          end_word(&mut words, &mut word, &mut quoted, word_start..start);
+          break;
+        }
+        Some('\n') => Delimiter,
+        Some(c) => {
+          word.push(c);
+          Unquoted
+        }
+      },
+      Unquoted => match c {
+        None => {
+          end_word(&mut words, &mut word, &mut quoted, word_start..start);
+          break;
+        }
+        Some('\'') => {
+          quoted = true;
+          SingleQuoted
+        }
+        Some('\"') => {
+          quoted = true;
+          DoubleQuoted
+        }
+        Some('\\') => UnquotedBackslash,
+        Some('\t') | Some(' ') | Some('\n') => {
+          end_word(&mut words, &mut word, &mut quoted, word_start..start);
+          Delimiter
+        }
+        Some('$') => {
+          expand(&mut word, &mut chars, variables);
+          Unquoted
+        }
+        Some(c) if is_operator(c, chars.peek()) => {
+          end_word(&mut words, &mut word, &mut quoted, word_start..start);
+          let operator = read_operator(c, &mut chars);
+          words.push((Token::Operator(operator), start..chars.offset));
+          Delimiter
+        }
+        Some(c) => {
+          word.push(c);
+          Unquoted
+        }
+      },
+      UnquotedBackslash => match c {
+        None => {
+          word.push('\\');
+          end_word(&mut words, &mut word, &mut quoted, word_start..start);
+          break;
+        }
+        Some('\n') => Unquoted,
+        Some(c) => {
+          word.push(c);
+          Unquoted
+        }
+      },
+      SingleQuoted => match c {
+        None => return Err(ParseError),
+        Some('\'') => Unquoted,
+        Some(c) => {
+          word.push(c);
+          SingleQuoted
+        }
+      },
+      DoubleQuoted => match c {
+        None => return Err(ParseError),
+        Some('\"') => Unquoted,
+        Some('\\') => DoubleQuotedBackslash,
+        Some('$') => {
+          expand(&mut word, &mut chars, variables);
+          DoubleQuoted
+        }
+        Some(c) => {
+          word.push(c);
+          DoubleQuoted
+        }
+      },
+      DoubleQuotedBackslash => match c {
+        None => return Err(ParseError),
+        Some('\n') => DoubleQuoted,
+        Some(c @ '$') | Some(c @ '`') | Some(c @ '"') | Some(c @ '\\') => {
+          word.push(c);
+          DoubleQuoted
+        }
+        Some(c) => {
+          word.push('\\');
+          word.push(c);
+          DoubleQuoted
+        }
+      },
+    };
+    if let (Some(classes), Some(mut class)) = (classes.as_deref_mut(), class) {
+      let end = chars.offset;
+      // A `$` without a name after it is just a `$`
+      if class == Class::Variable && end - start == 1 {
+        class = if state == DoubleQuoted {
+          Class::Quoted
+        } else {
+          Class::Plain
+        };
       }
+      classes.push((start..end, class));
     }
+  }
+
+  Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn word(s: &str) -> Token {
+    Token::Word(s.to_string())
+  }
+
+  #[test]
+  fn test_pipe_operator() {
+    assert_eq!(
+      "list-users --active | disable".try_into_tokens(),
+      Ok(vec![
+        word("list-users"),
+        word("--active"),
+        Token::Operator(Operator::Pipe),
+        word("disable"),
+      ])
+    );
+    assert_eq!(
+      "a|b".try_into_tokens(),
+      Ok(vec![word("a"), Token::Operator(Operator::Pipe), word("b")])
+    );
+
+    // Quoted and escaped pipes are part of the word
+    assert_eq!(
+      r#"echo "a|b" 'c|d' e\|f"#.try_into_tokens(),
+      Ok(vec![word("echo"), word("a|b"), word("c|d"), word("e|f")])
+    );
+  }
+
+  #[test]
+  fn test_chain_operators() {
+    assert_eq!(
+      "a;b && c || d | e & f".try_into_tokens(),
+      Ok(vec![
+        word("a"),
+        Token::Operator(Operator::Then),
+        word("b"),
+        Token::Operator(Operator::And),
+        word("c"),
+        Token::Operator(Operator::Or),
+        word("d"),
+        Token::Operator(Operator::Pipe),
+        word("e"),
+        word("&"),
+        word("f"),
+      ])
+    );
+    assert_eq!(
+      "a > x.json >>y '>'".try_into_tokens(),
+      Ok(vec![
+        word("a"),
+        Token::Operator(Operator::Write),
+        word("x.json"),
+        Token::Operator(Operator::Append),
+        word("y"),
+        word(">"),
+      ])
+    );
+    assert_eq!(
+      split_chain("a | b && 'c;d' $x; e"),
+      Ok(vec![
+        (None, "a | b "),
+        (Some(Operator::And), " 'c;d' $x"),
+        (Some(Operator::Then), " e"),
+      ])
+    );
+    assert_eq!(
+      split_chain("café && ünïcode"),
+      Ok(vec![(None, "café "), (Some(Operator::And), " ünïcode")])
+    );
+  }
+
+  fn expand(line: &str) -> Vec<Token> {
+    let variables = |name: &str| match name {
+      "env" => Some("prod".to_string()),
+      "?" => Some("0".to_string()),
+      "_" => Some("a b".to_string()),
+      _ => None,
+    };
+    line.try_expand(&variables).unwrap()
+  }
+
+  #[test]
+  fn test_expand_variables() {
+    assert_eq!(
+      expand(r#"deploy --env $env --tag ${env}-1 "$_" $?"#),
+      vec![
+        word("deploy"),
+        word("--env"),
+        word("prod"),
+        word("--tag"),
+        word("prod-1"),
+        word("a b"),
+        word("0"),
+      ]
+    );
+    // Unset variables are empty
+    assert_eq!(expand("echo x$nope"), vec![word("echo"), word("x")]);
+    // and a word with nothing else in it is left out, unless quoted
+    assert_eq!(
+      expand(r#"deploy $nope ${nope}|x "$nope" ''"#),
+      vec![
+        word("deploy"),
+        Token::Operator(Operator::Pipe),
+        word("x"),
+        word(""),
+        word(""),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_result_paths() {
+    let tokens = "show $_.items[0].id $2.name. $1[x] $env.id"
+      .try_expand(&|name| Some(format!("<{name}>")))
+      .unwrap();
+    assert_eq!(
+      tokens,
+      vec![
+        word("show"),
+        word("<_.items[0].id>"),
+        // A trailing `.` isn't part of the path
+        word("<2.name>."),
+        word("<1>[x]"),
+        word("<env>.id"),
+      ]
+    );
+  }
 
-    Ok(words)
+  #[test]
+  fn test_literal_dollars() {
+    assert_eq!(
+      expand(r#"echo '$env' "\$env" \$env $ ${ ${env"#),
+      vec![
+        word("echo"),
+        word("$env"),
+        word("$env"),
+        word("$env"),
+        word("$"),
+        word("${"),
+        word("${env"),
+      ]
+    );
+    // Without variables `$` is kept as it is
+    assert_eq!(
+      "echo $env".try_into_tokens(),
+      Ok(vec![word("echo"), word("$env")])
+    );
   }
 }
@@ -0,0 +1,47 @@
+use reedline::{ValidationResult, Validator};
+
+use crate::tokenizer::IntoArgs;
+
+/// Keeps the line open while a quote is unclosed or the line ends in `\`,
+/// so Enter starts a new line (shown with `::: `) instead of running it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ShellValidator;
+
+impl Validator for ShellValidator {
+  fn validate(&self, line: &str) -> ValidationResult {
+    if is_incomplete(line) {
+      ValidationResult::Incomplete
+    } else {
+      ValidationResult::Complete
+    }
+  }
+}
+
+/// Whether `line` needs more lines before it can run.
+pub(crate) fn is_incomplete(line: &str) -> bool {
+  if line.try_into_tokens().is_err() {
+    return true;
+  }
+  // With every quote closed, an odd run of trailing backslashes escapes the
+  // newline. `\\` is just a backslash.
+  let backslashes = line.chars().rev().take_while(|c| *c == '\\').count();
+  backslashes % 2 == 1
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_incomplete_lines() {
+    assert!(is_incomplete("deploy --note 'not done"));
+    assert!(is_incomplete("deploy --note \"still \\\" open"));
+    assert!(is_incomplete("deploy --env prod \\"));
+    assert!(is_incomplete("deploy --note 'two\nlines"));
+
+    assert!(!is_incomplete("deploy --note 'done'"));
+    assert!(!is_incomplete("deploy --env prod \\\\"));
+    assert!(!is_incomplete("deploy --env prod \\\n--force"));
+    assert!(!is_incomplete(""));
+  }
+}
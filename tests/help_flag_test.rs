@@ -1,4 +1,4 @@
-use mysh::{CommandArg, Scripts, command};
+use mysh::{CommandArg, Scripts, Shell, command};
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone)]
@@ -30,8 +30,17 @@ async fn test_root_command_help_flag() {
 
 #[tokio::test]
 async fn test_subcommand_help_flag() {
-  // This test verifies that subcommands can handle --help flags
-  // We can't easily test the interactive shell, but we've verified
-  // the code logic is correct through code review
-  assert!(true);
+  let shell = Shell::new(TestInfo {}).add_subcommand(
+    "outer",
+    Scripts::new(TestInfo {})
+      .add_command(test_cmd)
+      .add_subcommand("inner", Scripts::new(TestInfo {}).add_command(test_cmd)),
+  );
+
+  assert!(shell.run_command("outer --help").await.is_ok());
+  assert!(shell.run_command("outer test_cmd --help").await.is_ok());
+  assert!(shell.run_command("outer inner --help").await.is_ok());
+  assert!(shell.run_command("outer inner test_cmd -h").await.is_ok());
+  assert!(shell.run_command("help outer inner").await.is_ok());
+  assert!(shell.run_command("outer inner nope --help").await.is_err());
 }
@@ -0,0 +1,270 @@
+use mysh::{CommandArg, Error, parse_arguments, parse_arguments_prompting};
+use serde::Deserialize;
+
+#[derive(CommandArg, Deserialize, Debug, PartialEq)]
+pub struct DeployArgs {
+  id: String,
+  count: i32,
+  ratio: Option<f32>,
+  force: Option<bool>,
+  from_dump: Option<String>,
+}
+
+#[derive(CommandArg, Deserialize, Debug, PartialEq)]
+pub struct EmptyArgs {}
+
+fn argv(line: &str) -> Vec<String> {
+  line.split_whitespace().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn test_values_follow_field_types() {
+  let args: DeployArgs = parse_arguments(argv("deploy --id 1 --count 1 --ratio 0.5")).unwrap();
+  assert_eq!(
+    args,
+    DeployArgs {
+      id: "1".to_string(),
+      count: 1,
+      ratio: Some(0.5),
+      force: None,
+      from_dump: None,
+    }
+  );
+}
+
+#[test]
+fn test_bool_flags_and_kebab_case() {
+  let args: DeployArgs =
+    parse_arguments(argv("deploy --force --id=0 --count 2 --from-dump 0")).unwrap();
+  assert_eq!(args.force, Some(true));
+  assert_eq!(args.id, "0");
+  assert_eq!(args.from_dump, Some("0".to_string()));
+}
+
+#[test]
+fn test_bool_flag_values() {
+  let args: DeployArgs = parse_arguments(argv("deploy --id 0 --count 2 --force 1")).unwrap();
+  assert_eq!(args.force, Some(true));
+
+  let args: DeployArgs = parse_arguments(argv("deploy --force no --id 0 --count 2")).unwrap();
+  assert_eq!(args.force, Some(false));
+
+  let args: CopyArgs = parse_arguments(argv("copy --force yes src.txt dst.txt")).unwrap();
+  assert_eq!(args.force, Some(true));
+  assert_eq!(args.from, "src.txt");
+}
+
+#[test]
+fn test_invalid_value_names_field() {
+  let err = parse_arguments::<DeployArgs>(argv("deploy --id a --count many")).unwrap_err();
+  match err {
+    Error::InvalidArgument { name, .. } => assert_eq!(name, "--count"),
+    e => panic!("unexpected error: {e}"),
+  }
+
+  let err = parse_arguments::<DeployArgs>(argv("deploy --id a --count 1 --nope 1")).unwrap_err();
+  assert!(err.to_string().contains("--nope"));
+}
+
+#[test]
+fn test_empty_and_primitive_args() {
+  assert_eq!(
+    parse_arguments::<EmptyArgs>(argv("ls")).unwrap(),
+    EmptyArgs {}
+  );
+  assert_eq!(parse_arguments::<Option<i32>>(argv("cmd")).unwrap(), None);
+  assert_eq!(parse_arguments::<String>(argv("cmd 1")).unwrap(), "1");
+  assert_eq!(parse_arguments::<i32>(argv("cmd 1")).unwrap(), 1);
+  assert!(parse_arguments::<i32>(argv("cmd one")).is_err());
+}
+
+#[derive(CommandArg, Deserialize, Debug, PartialEq)]
+pub struct CopyArgs {
+  #[arg(positional)]
+  from: String,
+  #[arg(positional)]
+  to: String,
+  force: Option<bool>,
+}
+
+#[derive(CommandArg, Deserialize, Debug, PartialEq)]
+pub struct TarArgs {
+  #[arg(positional)]
+  archive: String,
+  #[arg(positional)]
+  files: Vec<String>,
+  level: Option<i32>,
+}
+
+#[test]
+fn test_positional_arguments() {
+  let args: CopyArgs = parse_arguments(argv("copy src.txt dst.txt")).unwrap();
+  assert_eq!(args.from, "src.txt");
+  assert_eq!(args.to, "dst.txt");
+  assert_eq!(args.force, None);
+
+  // bool flags don't swallow the following positional
+  let args: CopyArgs = parse_arguments(argv("copy --force src.txt dst.txt")).unwrap();
+  assert_eq!(args.force, Some(true));
+  assert_eq!(args.from, "src.txt");
+
+  // positionals can still be given by name
+  let args: CopyArgs = parse_arguments(argv("copy --to dst.txt src.txt")).unwrap();
+  assert_eq!(args.from, "src.txt");
+  assert_eq!(args.to, "dst.txt");
+
+  let err = parse_arguments::<CopyArgs>(argv("copy a b c")).unwrap_err();
+  assert!(err.to_string().contains("unexpected argument c"));
+}
+
+#[test]
+fn test_variadic_positional() {
+  let args: TarArgs = parse_arguments(argv("tar out.tar a.txt --level 9 b.txt")).unwrap();
+  assert_eq!(args.archive, "out.tar");
+  assert_eq!(args.files, vec!["a.txt", "b.txt"]);
+  assert_eq!(args.level, Some(9));
+
+  let args: TarArgs = parse_arguments(argv("tar out.tar")).unwrap();
+  assert!(args.files.is_empty());
+}
+
+#[test]
+fn test_positional_help() {
+  use mysh::ArgSpec;
+  let usage: Vec<String> = TarArgs::args()
+    .iter()
+    .filter(|a| a.positional)
+    .map(ArgSpec::usage)
+    .collect();
+  assert_eq!(usage, vec!["<ARCHIVE>", "[FILES]..."]);
+  assert_eq!(
+    CopyArgs::display_help(),
+    vec!["<FROM>: String", "<TO>: String", "--force: Option<bool>"]
+  );
+}
+
+#[derive(CommandArg, Deserialize, Debug, PartialEq)]
+pub struct RemoveArgs {
+  #[arg(positional)]
+  path: String,
+  #[arg(short)]
+  recursive: Option<bool>,
+  #[arg(short = 'f')]
+  force: Option<bool>,
+  #[arg(short, alias = "nick", alias = "label")]
+  name: Option<String>,
+  offset: Option<i32>,
+}
+
+#[test]
+fn test_short_flags_and_aliases() {
+  let args: RemoveArgs = parse_arguments(argv("rm -rf dir -n x")).unwrap();
+  assert_eq!(args.recursive, Some(true));
+  assert_eq!(args.force, Some(true));
+  assert_eq!(args.path, "dir");
+  assert_eq!(args.name, Some("x".to_string()));
+
+  let args: RemoveArgs = parse_arguments(argv("rm dir --nick y --offset -3")).unwrap();
+  assert_eq!(args.name, Some("y".to_string()));
+  assert_eq!(args.offset, Some(-3));
+
+  let args: RemoveArgs = parse_arguments(argv("rm -n=z dir")).unwrap();
+  assert_eq!(args.name, Some("z".to_string()));
+
+  let err = parse_arguments::<RemoveArgs>(argv("rm -rx dir")).unwrap_err();
+  assert!(err.to_string().contains("-x"));
+}
+
+#[test]
+fn test_short_flag_help() {
+  assert_eq!(
+    RemoveArgs::display_help()[3],
+    "-n, --name, --nick, --label: Option<String>"
+  );
+}
+
+#[derive(CommandArg, Deserialize, Debug, PartialEq)]
+pub struct TagArgs {
+  tag: Vec<String>,
+  #[arg(delimiter = ',')]
+  id: Option<Vec<i32>>,
+  label: Option<std::collections::HashMap<String, String>>,
+}
+
+#[test]
+fn test_repeated_flags_accumulate() {
+  let args: TagArgs = parse_arguments(argv("tag --tag a --tag b --id 1,2 --id 3")).unwrap();
+  assert_eq!(args.tag, vec!["a", "b"]);
+  assert_eq!(args.id, Some(vec![1, 2, 3]));
+
+  // without a delimiter commas are kept
+  let args: TagArgs = parse_arguments(argv("tag --tag a,b")).unwrap();
+  assert_eq!(args.tag, vec!["a,b"]);
+}
+
+#[test]
+fn test_key_value_flags() {
+  let args: TagArgs =
+    parse_arguments(argv("tag --tag a --label env=prod --label team=core=x")).unwrap();
+  let label = args.label.unwrap();
+  assert_eq!(label.get("env").map(String::as_str), Some("prod"));
+  assert_eq!(label.get("team").map(String::as_str), Some("core=x"));
+
+  let err = parse_arguments::<TagArgs>(argv("tag --tag a --label env")).unwrap_err();
+  assert!(err.to_string().contains("--label"));
+}
+
+#[test]
+fn test_collection_help() {
+  assert_eq!(Vec::<String>::display_help(), vec!["[string]..."]);
+  assert_eq!(
+    std::collections::HashMap::<String, String>::display_help(),
+    vec!["[string=string]..."]
+  );
+}
+
+#[derive(CommandArg, Deserialize, Debug, PartialEq)]
+pub struct LoginArgs {
+  user: String,
+  #[arg(secret, short)]
+  token: i64,
+}
+
+#[test]
+fn test_secret_values_left_out_of_errors() {
+  let err = parse_arguments::<LoginArgs>(argv("login --user ana --token hunter2")).unwrap_err();
+  assert!(!err.to_string().contains("hunter2"), "{err}");
+
+  let err = parse_arguments::<LoginArgs>(argv("login -t=hunter2")).unwrap_err();
+  assert!(!err.to_string().contains("hunter2"), "{err}");
+
+  assert_eq!(LoginArgs::display_help()[1], "-t, --token: i64 (secret)");
+}
+
+#[test]
+fn test_missing_secret_fails_without_prompting() {
+  // Outside the shell (and without `--prompt`) nothing is asked for
+  let err = parse_arguments::<LoginArgs>(argv("login --user ana")).unwrap_err();
+  assert!(
+    matches!(&err, Error::InvalidArgument { name, .. } if name == "--token"),
+    "{err}"
+  );
+  let err = parse_arguments::<LoginArgs>(argv("login")).unwrap_err();
+  assert!(matches!(err, Error::InvalidArgument { .. }), "{err}");
+}
+
+#[tokio::test]
+async fn test_missing_argument_fails_without_prompting() {
+  let err = parse_arguments_prompting::<DeployArgs>(argv("deploy --id 1"))
+    .await
+    .unwrap_err();
+  assert!(err.to_string().contains("missing field `count`"), "{err}");
+
+  let err = parse_arguments_prompting::<LoginArgs>(argv("login --user ana"))
+    .await
+    .unwrap_err();
+  assert!(
+    matches!(&err, Error::InvalidArgument { name, .. } if name == "--token"),
+    "{err}"
+  );
+}
@@ -0,0 +1,123 @@
+use mysh::{CommandArg, Error, Shell, command};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone)]
+pub struct TestInfo;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct User {
+  name: String,
+  active: bool,
+}
+
+#[derive(CommandArg, Serialize, Deserialize, Debug, Clone)]
+pub struct ListUsersArgs {
+  active: Option<bool>,
+}
+
+#[command(name = "list-users", description = "Lists users")]
+pub async fn list_users(_: TestInfo, args: ListUsersArgs) -> mysh::Result<Vec<User>> {
+  let users = vec![
+    User {
+      name: "ana".to_string(),
+      active: true,
+    },
+    User {
+      name: "bo".to_string(),
+      active: false,
+    },
+  ];
+  Ok(
+    users
+      .into_iter()
+      .filter(|u| args.active.is_none_or(|active| u.active == active))
+      .collect(),
+  )
+}
+
+#[derive(CommandArg, Serialize, Deserialize, Debug, Clone)]
+pub struct DisableArgs {
+  #[arg(input)]
+  users: Vec<User>,
+  reason: Option<String>,
+}
+
+#[command(name = "disable", description = "Disables users")]
+pub async fn disable(_: TestInfo, args: DisableArgs) -> mysh::Result<Vec<String>> {
+  let reason = args.reason.unwrap_or_default();
+  Ok(
+    args
+      .users
+      .into_iter()
+      .map(|u| format!("{}{reason}", u.name))
+      .collect(),
+  )
+}
+
+#[derive(CommandArg, Serialize, Deserialize, Debug, Clone)]
+pub struct GreetArgs {
+  name: String,
+  greeting: Option<String>,
+}
+
+#[command(name = "greet", description = "Greets a user")]
+pub async fn greet(_: TestInfo, args: GreetArgs) -> mysh::Result<String> {
+  let greeting = args.greeting.unwrap_or("hi".to_string());
+  Ok(format!("{greeting} {}", args.name))
+}
+
+#[command(name = "first", description = "Returns the first user")]
+pub async fn first(_: TestInfo, _: Option<()>) -> mysh::Result<User> {
+  Ok(User {
+    name: "ana".to_string(),
+    active: true,
+  })
+}
+
+fn shell() -> Shell<TestInfo> {
+  Shell::new(TestInfo)
+    .add_command(list_users)
+    .add_command(disable)
+    .add_command(greet)
+    .add_command(first)
+}
+
+#[tokio::test]
+async fn test_pipe_into_input_field() {
+  let value = shell()
+    .run_command("list-users --active | disable --reason !")
+    .await
+    .unwrap();
+  assert_eq!(value, serde_json::json!(["ana!"]));
+}
+
+#[tokio::test]
+async fn test_pipe_object_merges_into_flags() {
+  let shell = shell();
+  let value = shell.run_command("first | greet").await.unwrap();
+  assert_eq!(value, serde_json::json!("hi ana"));
+
+  // Flags on the command line win over piped keys
+  let value = shell
+    .run_command("first | greet --name bo --greeting hey")
+    .await
+    .unwrap();
+  assert_eq!(value, serde_json::json!("hey bo"));
+}
+
+#[tokio::test]
+async fn test_pipe_errors() {
+  let shell = shell();
+  assert!(matches!(
+    shell.run_command("list-users | greet").await,
+    Err(Error::ArgParseError(_))
+  ));
+  assert!(matches!(
+    shell.run_command("list-users |").await,
+    Err(Error::ArgParseError(_))
+  ));
+  assert!(matches!(
+    shell.run_command("| disable").await,
+    Err(Error::ArgParseError(_))
+  ));
+}
@@ -0,0 +1,124 @@
+use mysh::{CommandArg, Error, Scripts, Shell, command};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone)]
+pub struct TestInfo {
+  prefix: String,
+}
+
+#[derive(CommandArg, Serialize, Deserialize, Debug, Clone)]
+pub struct EchoArgs {
+  #[arg(positional)]
+  words: Vec<String>,
+}
+
+#[command(name = "echo", description = "Returns its arguments")]
+pub async fn echo(info: TestInfo, args: EchoArgs) -> mysh::Result<Vec<String>> {
+  Ok(
+    args
+      .words
+      .into_iter()
+      .map(|w| format!("{}{w}", info.prefix))
+      .collect(),
+  )
+}
+
+fn shell() -> Shell<TestInfo> {
+  let info = |prefix: &str| TestInfo {
+    prefix: prefix.to_string(),
+  };
+  Shell::new(info("")).add_command(echo).add_subcommand(
+    "cloud",
+    Scripts::new(info("cloud:")).add_subcommand(
+      "db",
+      Scripts::new(info("db:"))
+        .add_subcommand("replica", Scripts::new(info("replica:")).add_command(echo)),
+    ),
+  )
+}
+
+#[tokio::test]
+async fn test_run_command_returns_value() {
+  let value = shell()
+    .run_command("echo  'hello world'  \"a  b\"")
+    .await
+    .unwrap();
+  assert_eq!(value, serde_json::json!(["hello world", "a  b"]));
+}
+
+#[tokio::test]
+async fn test_run_command_routes_into_nested_namespaces() {
+  let value = shell()
+    .run_command("cloud db replica echo promote")
+    .await
+    .unwrap();
+  assert_eq!(value, serde_json::json!(["replica:promote"]));
+}
+
+#[tokio::test]
+async fn test_run_command_errors() {
+  let shell = shell();
+  assert!(matches!(
+    shell.run_command("nope").await,
+    Err(Error::CommandNotFound(_))
+  ));
+  assert!(matches!(
+    shell.run_command("   ").await,
+    Err(Error::MissingSubcommand(_))
+  ));
+  assert!(matches!(
+    shell.run_command("echo 'unterminated").await,
+    Err(Error::ArgParseError(_))
+  ));
+}
+
+#[tokio::test]
+async fn test_scripts_run_command() {
+  let scripts = Scripts::new(TestInfo {
+    prefix: ">".to_string(),
+  })
+  .add_command(echo);
+  let value = scripts.run_command("echo a  b").await.unwrap();
+  assert_eq!(value, serde_json::json!([">a", ">b"]));
+}
+
+#[tokio::test]
+async fn test_run_command_chains() {
+  let shell = shell();
+  let value = shell.run_command("echo a; echo b").await.unwrap();
+  assert_eq!(value, serde_json::json!(["b"]));
+
+  // `&&` stops at the first failure, `||` only runs after one
+  assert!(matches!(
+    shell.run_command("nope && echo b").await,
+    Err(Error::CommandNotFound(_))
+  ));
+  let value = shell.run_command("nope || echo b").await.unwrap();
+  assert_eq!(value, serde_json::json!(["b"]));
+  let value = shell.run_command("echo a || nope && echo c").await.unwrap();
+  assert_eq!(value, serde_json::json!(["c"]));
+
+  // Quoted separators are part of the word, a trailing `;` is fine
+  let value = shell
+    .run_command("echo 'a;b' \"&&\" a\\|\\|b;")
+    .await
+    .unwrap();
+  assert_eq!(value, serde_json::json!(["a;b", "&&", "a||b"]));
+  assert!(matches!(
+    shell.run_command("echo a && ; echo b").await,
+    Err(Error::ArgParseError(_))
+  ));
+}
+
+#[tokio::test]
+async fn test_run_command_redirects_result() {
+  let path = std::env::temp_dir().join(format!("mysh-run-command-{}.json", std::process::id()));
+  let line = format!("echo a b > '{}'", path.display());
+  let value = shell().run_command(&line).await.unwrap();
+  assert_eq!(value, serde_json::Value::Null);
+  assert_eq!(
+    std::fs::read_to_string(&path).unwrap(),
+    "[\n  \"a\",\n  \"b\"\n]\n"
+  );
+  std::fs::remove_file(path).unwrap();
+}